@@ -0,0 +1,224 @@
+use crate::config::{IpType, RetryConfig};
+use crate::core::Context;
+use crate::dns::DnsResolver;
+use crate::errors::{DnessError, DnsError, DnsErrorKind};
+use log::{debug, info};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A resolver for each authoritative name server that has been asked, so that they are created
+/// once rather than for every lookup. Lookups through them skip the cache, so reusing them never
+/// gives a stale answer.
+#[derive(Default)]
+pub struct NameServers {
+    resolvers: Mutex<HashMap<IpAddr, Arc<DnsResolver>>>,
+}
+
+impl std::fmt::Debug for NameServers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let servers = self.resolvers.lock().unwrap();
+        f.debug_set().entries(servers.keys()).finish()
+    }
+}
+
+impl NameServers {
+    async fn resolver(
+        &self,
+        server: IpAddr,
+        retry: &RetryConfig,
+    ) -> Result<Arc<DnsResolver>, DnsError> {
+        if let Some(resolver) = self.resolvers.lock().unwrap().get(&server) {
+            return Ok(Arc::clone(resolver));
+        }
+
+        let resolver = Arc::new(DnsResolver::from_ips(&[server]).await?.with_retry(retry));
+        let mut resolvers = self.resolvers.lock().unwrap();
+        Ok(Arc::clone(resolvers.entry(server).or_insert(resolver)))
+    }
+}
+
+/// The names that the zone of the host could be, from the host itself up to, but not including,
+/// the top level domain
+fn candidate_zones(host: &str) -> Vec<&str> {
+    let mut zones = Vec::new();
+    let mut name = host.trim_end_matches('.');
+    while let Some((_, parent)) = name.split_once('.') {
+        zones.push(name);
+        name = parent;
+    }
+    zones
+}
+
+/// The addresses of the name servers that are authoritative for the host's zone
+async fn name_servers(resolver: &DnsResolver, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+    for zone in candidate_zones(host) {
+        let names = match resolver.ns_lookup(&format!("{}.", zone)).await {
+            Ok(names) if !names.is_empty() => names,
+            Ok(_) => continue,
+            Err(e) if e.is_not_found() => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut servers = Vec::new();
+        for name in &names {
+            for ip_type in [IpType::V4, IpType::V6] {
+                match resolver.ip_lookup(name, ip_type).await {
+                    Ok(ip) => servers.push(ip),
+                    Err(e) if e.is_not_found() => {}
+                    Err(e) => debug!("could not resolve name server {}: {}", name, e),
+                }
+            }
+        }
+
+        if !servers.is_empty() {
+            debug!("{} is served by {} ({})", host, zone, names.join(", "));
+            return Ok(servers);
+        }
+    }
+
+    Err(DnsError {
+        kind: Box::new(DnsErrorKind::NoNameServers(String::from(host))),
+    })
+}
+
+/// Asks each authoritative name server for the address of the host. Servers that can't be reached
+/// are skipped, so that only when none answer is the error returned.
+async fn authoritative_ips(
+    ctx: &Context<'_>,
+    host: &str,
+    ip_type: IpType,
+) -> Result<Vec<IpAddr>, DnsError> {
+    let servers = name_servers(ctx.resolver, host).await?;
+    let lookups = servers.iter().map(|server| async move {
+        let resolver = ctx.name_servers.resolver(*server, ctx.retry).await?;
+        resolver.direct_ip_lookup(host, ip_type).await
+    });
+
+    let mut ips = Vec::new();
+    let mut first_err = None;
+    for (server, result) in servers.iter().zip(futures::future::join_all(lookups).await) {
+        match result {
+            Ok(ip) => ips.push(ip),
+            Err(e) => {
+                debug!("name server {} did not answer for {}: {}", server, host, e);
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_err {
+        Some(e) if ips.is_empty() => Err(e),
+        _ => Ok(ips),
+    }
+}
+
+/// The address to compare against the WAN IP: the first one that differs from it, so that a record
+/// is only current once every name server agrees
+fn pick(ips: &[IpAddr], wan: IpAddr) -> IpAddr {
+    ips.iter().copied().find(|ip| *ip != wan).unwrap_or(wan)
+}
+
+/// The current address of the host, from either the public resolver or, when configured, the
+/// authoritative name servers of its zone
+pub async fn current_ip(ctx: &Context<'_>, host: &str, wan: IpAddr) -> Result<IpAddr, DnsError> {
+    if !ctx.lookup.authoritative {
+        return ctx.resolver.ip_lookup(host, wan.into()).await;
+    }
+
+    let ips = authoritative_ips(ctx, host, wan.into()).await?;
+    Ok(pick(&ips, wan))
+}
+
+/// When configured, waits for the authoritative name servers to answer with the new address for
+/// every updated host, failing with those that didn't converge in time
+pub async fn verify(ctx: &Context<'_>, hosts: &[String], wan: IpAddr) -> Result<(), DnessError> {
+    if !ctx.lookup.verify || ctx.dry_run || hosts.is_empty() {
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let timeout = Duration::from_secs(ctx.lookup.verify_timeout);
+    let interval = Duration::from_secs(ctx.lookup.verify_interval);
+    let mut pending: Vec<&String> = hosts.iter().collect();
+    loop {
+        let mut unconverged = Vec::new();
+        for host in pending {
+            let dns_query = format!("{}.", host.trim_end_matches('.'));
+            match authoritative_ips(ctx, &dns_query, wan.into()).await {
+                Ok(ips) if pick(&ips, wan) == wan => {}
+                Ok(ips) => {
+                    debug!("{} is still served as {:?}", host, ips);
+                    unconverged.push(host);
+                }
+                Err(e) => {
+                    debug!("could not verify {}: {}", host, e);
+                    unconverged.push(host);
+                }
+            }
+        }
+
+        pending = unconverged;
+        if pending.is_empty() {
+            info!(
+                "verified {} record(s) with the authoritative name servers in {:.1}s",
+                hosts.len(),
+                start.elapsed().as_secs_f64()
+            );
+            return Ok(());
+        }
+
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            let names: Vec<&str> = pending.iter().map(|x| x.as_str()).collect();
+            return Err(DnessError::message(format!(
+                "records did not converge on {} within {}s: {}",
+                wan,
+                ctx.lookup.verify_timeout,
+                names.join(", ")
+            )));
+        }
+
+        tokio::time::sleep(remaining.min(interval)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn zones_of_host() {
+        assert_eq!(
+            candidate_zones("www.example.co.uk."),
+            vec!["www.example.co.uk", "example.co.uk", "co.uk"]
+        );
+        assert_eq!(candidate_zones("example.com"), vec!["example.com"]);
+        assert!(candidate_zones("com.").is_empty());
+    }
+
+    #[test]
+    fn pick_disagreeing_answer() {
+        let wan = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let old = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        assert_eq!(pick(&[wan, wan], wan), wan);
+        assert_eq!(pick(&[wan, old], wan), old);
+        assert_eq!(pick(&[], wan), wan);
+    }
+
+    #[tokio::test]
+    async fn name_server_resolvers_reused() {
+        let servers = NameServers::default();
+        let retry = RetryConfig::default();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53));
+        let first = servers.resolver(ip, &retry).await.unwrap();
+        let second = servers.resolver(ip, &retry).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 54));
+        let third = servers.resolver(other, &retry).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+    }
+}
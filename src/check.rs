@@ -0,0 +1,613 @@
+use crate::config::{DnsConfig, DomainConfig, IpType, PROVIDERS};
+use crate::core::{fqdn, Context};
+use crate::errors::describe_error;
+use crate::{cloudflare, dynu, godaddy, he, namecheap, noip, porkbun, prefix, rfc2136};
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+/// The outcome of checking a single section of the config
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub problems: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    fn new(name: String) -> Self {
+        Report {
+            name,
+            ..Report::default()
+        }
+    }
+
+    pub fn print(&self) {
+        if self.problems.is_empty() {
+            println!("{}: ok", self.name);
+        } else {
+            println!("{}: {} problem(s)", self.name, self.problems.len());
+        }
+
+        for problem in &self.problems {
+            println!("    - {}", problem);
+        }
+
+        for note in &self.notes {
+            println!("    note: {}", note);
+        }
+    }
+}
+
+/// Whether the name is a syntactically valid dns name, where the leftmost label may be a wildcard
+fn is_valid_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+
+    name.split('.').enumerate().all(|(i, label)| {
+        (i == 0 && label == "*")
+            || (!label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    })
+}
+
+fn require(value: &str, field: &str, problems: &mut Vec<String>) {
+    if value.trim().is_empty() {
+        problems.push(format!("missing {}", field));
+    }
+}
+
+fn check_domain_name(domain: &str, problems: &mut Vec<String>) {
+    if !is_valid_name(domain) || domain.starts_with('*') {
+        problems.push(format!("`{}` is not a valid domain name", domain));
+    }
+}
+
+/// Checks records that are written relative to the domain, eg: "@" and "sub"
+fn check_relative_records(records: &[String], domain: &str, problems: &mut Vec<String>) {
+    if records.is_empty() {
+        problems.push(String::from("no records configured"));
+    }
+
+    let suffix = format!(".{}", domain);
+    for record in records {
+        if record == domain || record.ends_with(&suffix) {
+            problems.push(format!(
+                "record `{}` should be relative to {} (eg: \"@\" or \"sub\")",
+                record, domain
+            ));
+        } else if !is_valid_name(&fqdn(record, domain)) {
+            problems.push(format!("record `{}` is not a valid name", record));
+        }
+    }
+}
+
+/// Checks records that are written as fully qualified names, eg: "sub.example.com". Without a
+/// zone, each record is matched to a zone later, so they can only be checked as names.
+fn check_qualified_records(records: &[String], zone: Option<&str>, problems: &mut Vec<String>) {
+    if records.is_empty() {
+        problems.push(String::from("no records configured"));
+    }
+
+    for record in records {
+        if !is_valid_name(record) {
+            problems.push(format!("record `{}` is not a valid name", record));
+        } else if let Some(zone) = zone {
+            if record != zone && !record.ends_with(&format!(".{}", zone)) {
+                problems.push(format!("record `{}` is not in zone {}", record, zone));
+            }
+        }
+    }
+}
+
+fn check_ip_types(
+    ip_types: &[IpType],
+    supported: &[IpType],
+    provider: &str,
+    problems: &mut Vec<String>,
+) {
+    if ip_types.is_empty() {
+        problems.push(String::from("no ip_types configured"));
+    }
+
+    for ip_type in ip_types {
+        if !supported.contains(ip_type) {
+            problems.push(format!(
+                "{} does not support {} records",
+                provider,
+                ip_type.record_type()
+            ));
+        }
+    }
+}
+
+/// Checks the top level settings that aren't tied to a domain
+pub fn validate_config(config: &DnsConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    if config.interval == 0 {
+        problems.push(String::from("interval must be at least one second"));
+    }
+    if config.max_concurrency == 0 {
+        problems.push(String::from("max_concurrency must be at least one"));
+    }
+    for (provider, limit) in &config.provider_concurrency {
+        if !PROVIDERS.contains(&provider.as_str()) {
+            problems.push(format!(
+                "provider_concurrency given for unknown provider {}",
+                provider
+            ));
+        } else if *limit == 0 {
+            problems.push(format!(
+                "provider_concurrency for {} must be at least one",
+                provider
+            ));
+        }
+    }
+    if config.http.connect_timeout == 0 || config.http.timeout == 0 {
+        problems.push(String::from("http timeouts must be at least one second"));
+    }
+    if config.hook_timeout == 0 {
+        problems.push(String::from("hook_timeout must be at least one second"));
+    }
+    if config.lookup.verify_interval == 0 {
+        problems.push(String::from("verify_interval must be at least one second"));
+    }
+    if config.retry.attempts == 0 {
+        problems.push(String::from("retry attempts must be at least one"));
+    }
+    if !(config.retry.base_delay >= 0.0 && config.retry.max_delay >= 0.0) {
+        problems.push(String::from("retry delays can't be negative"));
+    } else if config.retry.max_delay < config.retry.base_delay {
+        problems.push(String::from(
+            "retry max_delay can't be less than base_delay",
+        ));
+    }
+    problems
+}
+
+/// Checks that a domain's config is sensible without contacting the provider
+pub fn validate_domain(domain: &DomainConfig) -> Vec<String> {
+    let both = [IpType::V4, IpType::V6];
+    let mut problems = Vec::new();
+    match domain {
+        DomainConfig::Cloudflare(c) => {
+            let filled = |x: &Option<String>| x.as_ref().is_some_and(|x| !x.trim().is_empty());
+            if !(filled(&c.token) || filled(&c.email) && filled(&c.key)) {
+                problems.push(String::from("missing either token or email + key"));
+            }
+            if c.zone_id.as_ref().is_some_and(|x| x.trim().is_empty()) {
+                problems.push(String::from("zone_id is empty"));
+            }
+
+            if let Some(zone) = &c.zone {
+                check_domain_name(zone, &mut problems);
+            }
+            check_qualified_records(&c.records, c.zone.as_deref(), &mut problems);
+            check_ip_types(&c.ip_types, &both, "cloudflare", &mut problems);
+        }
+        DomainConfig::GoDaddy(c) => {
+            require(&c.key, "key", &mut problems);
+            require(&c.secret, "secret", &mut problems);
+            check_domain_name(&c.domain, &mut problems);
+            check_relative_records(&c.records, &c.domain, &mut problems);
+            check_ip_types(&c.ip_types, &both, "godaddy", &mut problems);
+        }
+        DomainConfig::Namecheap(c) => {
+            require(&c.ddns_password, "ddns_password", &mut problems);
+            check_domain_name(&c.domain, &mut problems);
+            check_relative_records(&c.records, &c.domain, &mut problems);
+        }
+        DomainConfig::He(c) => {
+            require(&c.password, "password", &mut problems);
+            check_domain_name(&c.hostname, &mut problems);
+            check_relative_records(&c.records, &c.hostname, &mut problems);
+            check_ip_types(&c.ip_types, &both, "he", &mut problems);
+        }
+        DomainConfig::NoIp(c) => {
+            require(&c.username, "username", &mut problems);
+            require(&c.password, "password", &mut problems);
+            check_domain_name(&c.hostname, &mut problems);
+            check_ip_types(&c.ip_types, &both, "noip", &mut problems);
+        }
+        DomainConfig::Dynu(c) => {
+            require(&c.username, "username", &mut problems);
+            require(&c.password, "password", &mut problems);
+            check_domain_name(&c.hostname, &mut problems);
+            check_relative_records(&c.records, &c.hostname, &mut problems);
+            check_ip_types(&c.ip_types, &both, "dynu", &mut problems);
+        }
+        DomainConfig::Porkbun(c) => {
+            require(&c.key, "key", &mut problems);
+            require(&c.secret, "secret", &mut problems);
+            check_domain_name(&c.domain, &mut problems);
+
+            // Porkbun also accepts an empty string for the root domain
+            let records: Vec<String> = c
+                .records
+                .iter()
+                .map(|x| {
+                    if x.is_empty() {
+                        String::from("@")
+                    } else {
+                        x.clone()
+                    }
+                })
+                .collect();
+            check_relative_records(&records, &c.domain, &mut problems);
+            check_ip_types(&c.ip_types, &both, "porkbun", &mut problems);
+        }
+        DomainConfig::Rfc2136(c) => {
+            require(&c.server, "server", &mut problems);
+            require(&c.key_name, "key_name", &mut problems);
+            require(&c.key_secret, "key_secret", &mut problems);
+            if rfc2136::decode_secret(&c.key_secret).is_err() {
+                problems.push(String::from("key_secret is not valid base64"));
+            }
+            check_domain_name(&c.zone, &mut problems);
+            check_relative_records(&c.records, &c.zone, &mut problems);
+            check_ip_types(&c.ip_types, &both, "rfc2136", &mut problems);
+        }
+    }
+
+    if let Some((suffixes, prefix_length)) = domain.ipv6_suffixes() {
+        check_suffixes(domain, suffixes, prefix_length, &mut problems);
+    }
+
+    problems
+}
+
+fn check_suffixes(
+    domain: &DomainConfig,
+    suffixes: &HashMap<String, Ipv6Addr>,
+    prefix_length: u8,
+    problems: &mut Vec<String>,
+) {
+    if suffixes.is_empty() {
+        return;
+    }
+
+    if prefix_length > 128 {
+        problems.push(format!("prefix_length of {} is over 128", prefix_length));
+        return;
+    }
+
+    if !domain.get_ip_types().contains(&IpType::V6) {
+        problems.push(String::from(
+            "suffixes are given but ip_types doesn't include \"6\"",
+        ));
+    }
+
+    let records = domain.records();
+    let mut names: Vec<&String> = suffixes.keys().collect();
+    names.sort();
+    for name in names {
+        if !records.contains(name) {
+            problems.push(format!("suffix given for {} which isn't in records", name));
+        }
+
+        // Bits of the suffix within the prefix would be silently dropped
+        let suffix = suffixes[name];
+        if prefix::combine(Ipv6Addr::UNSPECIFIED, prefix_length, suffix) != suffix {
+            problems.push(format!(
+                "suffix {} of {} overlaps the /{} prefix",
+                suffix, name, prefix_length
+            ));
+        }
+    }
+}
+
+/// Authenticates with the provider (where possible) and confirms that every configured record
+/// exists, without changing anything
+async fn verify_domain(ctx: &Context<'_>, domain: &DomainConfig, report: &mut Report) {
+    const UNVERIFIABLE: &str =
+        "credentials can't be verified without sending an update, only record existence was checked";
+
+    let creates_missing = match domain {
+        DomainConfig::Cloudflare(c) => c.create_missing,
+        DomainConfig::GoDaddy(c) => c.create_missing,
+        DomainConfig::Porkbun(c) => c.create_missing,
+        DomainConfig::Rfc2136(_) => true,
+        _ => false,
+    };
+
+    for ip_type in domain.get_ip_types() {
+        let missing = match domain {
+            DomainConfig::Cloudflare(c) => cloudflare::missing_records(ctx, c, ip_type)
+                .await
+                .map_err(|e| describe_error("could not query cloudflare", &e)),
+            DomainConfig::GoDaddy(c) => godaddy::missing_records(ctx, c, ip_type)
+                .await
+                .map_err(|e| describe_error("could not query godaddy", &e)),
+            DomainConfig::Porkbun(c) => porkbun::missing_records(ctx, c, ip_type)
+                .await
+                .map_err(|e| describe_error("could not query porkbun", &e)),
+            DomainConfig::Rfc2136(c) => rfc2136::missing_records(ctx, c, ip_type)
+                .await
+                .map_err(|e| describe_error("could not query the primary server", &e)),
+            DomainConfig::Namecheap(c) => Ok(namecheap::missing_records(ctx, c).await),
+            DomainConfig::He(c) => Ok(he::missing_records(ctx, c, ip_type).await),
+            DomainConfig::NoIp(c) => Ok(noip::missing_records(ctx, c, ip_type).await),
+            DomainConfig::Dynu(c) => Ok(dynu::missing_records(ctx, c, ip_type).await),
+        };
+
+        match missing {
+            Ok(missing) if creates_missing => report.notes.extend(missing.iter().map(|x| {
+                format!(
+                    "{} record `{}` not found and will be created",
+                    ip_type.record_type(),
+                    x
+                )
+            })),
+            Ok(missing) => report.problems.extend(
+                missing
+                    .iter()
+                    .map(|x| format!("{} record `{}` not found", ip_type.record_type(), x)),
+            ),
+            Err(e) => {
+                report.problems.push(e);
+
+                // No point in repeating the same authentication error for each ip type
+                break;
+            }
+        }
+    }
+
+    let unverifiable = matches!(
+        domain,
+        DomainConfig::Namecheap(_)
+            | DomainConfig::He(_)
+            | DomainConfig::NoIp(_)
+            | DomainConfig::Dynu(_)
+    );
+    if unverifiable {
+        report.notes.push(String::from(UNVERIFIABLE));
+    }
+}
+
+/// Checks every domain in the config, and when online, the providers too. The returned reports
+/// are in config order, with the top level settings first.
+pub async fn check(
+    ctx: &Context<'_>,
+    config: &DnsConfig,
+    mut general: Vec<String>,
+    online: bool,
+) -> Vec<Report> {
+    general.extend(validate_config(config));
+    let mut reports = vec![Report {
+        name: String::from("general"),
+        problems: general,
+        notes: Vec::new(),
+    }];
+
+    for domain in &config.domains {
+        let mut report = Report::new(domain.display_name());
+        report.problems = validate_domain(domain);
+
+        // Contacting a provider with a config known to be bad will only add noise
+        if online && report.problems.is_empty() {
+            verify_domain(ctx, domain, &mut report).await;
+        }
+
+        reports.push(report);
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CloudflareConfig, GoDaddyConfig, Rfc2136Config, TsigAlgorithm};
+    use std::collections::HashMap;
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("example.com"));
+        assert!(is_valid_name("example.com."));
+        assert!(is_valid_name("*.example.com"));
+        assert!(is_valid_name("_acme.example.com"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("a..example.com"));
+        assert!(!is_valid_name("-a.example.com"));
+        assert!(!is_valid_name("a b.example.com"));
+        assert!(!is_valid_name("a.*.example.com"));
+        assert!(!is_valid_name(&format!("{}.com", "a".repeat(64))));
+    }
+
+    #[test]
+    fn validate_cloudflare() {
+        let domain = DomainConfig::Cloudflare(CloudflareConfig {
+            base_url: String::from("https://api.cloudflare.com/client/v4"),
+            email: Some(String::from("admin@example.com")),
+            key: None,
+            token: None,
+            zone: Some(String::from("example.com")),
+            zone_id: None,
+            records: vec![
+                String::from("n.example.com"),
+                String::from("n.example2.com"),
+            ],
+            ip_types: vec![IpType::V4, IpType::V6],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 1,
+            proxied: None,
+            ttl: None,
+            comment: None,
+            tags: None,
+            overrides: HashMap::new(),
+        });
+
+        assert_eq!(
+            validate_domain(&domain),
+            vec![
+                String::from("missing either token or email + key"),
+                String::from("record `n.example2.com` is not in zone example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_godaddy() {
+        let domain = DomainConfig::GoDaddy(GoDaddyConfig {
+            base_url: String::from("https://api.godaddy.com"),
+            key: String::from("abc123"),
+            secret: String::from(""),
+            domain: String::from("example.com"),
+            records: vec![
+                String::from("@"),
+                String::from("*"),
+                String::from("a.example.com"),
+                String::from("a b"),
+            ],
+            ip_types: vec![],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
+        });
+
+        assert_eq!(
+            validate_domain(&domain),
+            vec![
+                String::from("missing secret"),
+                String::from(
+                    "record `a.example.com` should be relative to example.com (eg: \"@\" or \"sub\")"
+                ),
+                String::from("record `a b` is not a valid name"),
+                String::from("no ip_types configured"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rfc2136() {
+        let domain = DomainConfig::Rfc2136(Rfc2136Config {
+            server: String::from("ns1.example.com"),
+            zone: String::from("example.com"),
+            records: vec![String::from("@"), String::from("home")],
+            key_name: String::from("dness-key"),
+            key_secret: String::from("not base64!"),
+            key_algorithm: TsigAlgorithm::HmacSha256,
+            ttl: 300,
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+        });
+
+        assert_eq!(
+            validate_domain(&domain),
+            vec![String::from("key_secret is not valid base64")]
+        );
+    }
+
+    #[test]
+    fn validate_suffixes() {
+        let mut suffixes = HashMap::new();
+        suffixes.insert(String::from("nas"), "::1234".parse().unwrap());
+        suffixes.insert(String::from("old"), "::1".parse().unwrap());
+        suffixes.insert(String::from("www"), "::12:0:0:0:1".parse().unwrap());
+        let domain = DomainConfig::GoDaddy(GoDaddyConfig {
+            base_url: String::from("https://api.godaddy.com"),
+            key: String::from("abc123"),
+            secret: String::from("def456"),
+            domain: String::from("example.com"),
+            records: vec![String::from("nas"), String::from("www")],
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes,
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
+        });
+
+        assert_eq!(
+            validate_domain(&domain),
+            vec![
+                String::from("suffixes are given but ip_types doesn't include \"6\""),
+                String::from("suffix given for old which isn't in records"),
+                String::from("suffix ::12:0:0:0:1 of www overlaps the /64 prefix"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_concurrency() {
+        let mut config = DnsConfig {
+            max_concurrency: 0,
+            ..DnsConfig::default()
+        };
+        config
+            .provider_concurrency
+            .insert(String::from("godaddy"), 0);
+        config
+            .provider_concurrency
+            .insert(String::from("route53"), 1);
+        config
+            .provider_concurrency
+            .insert(String::from("porkbun"), 2);
+
+        assert_eq!(
+            validate_config(&config),
+            vec![
+                String::from("max_concurrency must be at least one"),
+                String::from("provider_concurrency for godaddy must be at least one"),
+                String::from("provider_concurrency given for unknown provider route53"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_http_and_retry() {
+        let mut config = DnsConfig::default();
+        config.http.timeout = 0;
+        config.retry.attempts = 0;
+        config.retry.max_delay = -1.0;
+        config.hook_timeout = 0;
+        config.lookup.verify_interval = 0;
+        assert_eq!(
+            validate_config(&config),
+            vec![
+                String::from("http timeouts must be at least one second"),
+                String::from("hook_timeout must be at least one second"),
+                String::from("verify_interval must be at least one second"),
+                String::from("retry attempts must be at least one"),
+                String::from("retry delays can't be negative"),
+            ]
+        );
+
+        let mut config = DnsConfig::default();
+        config.retry.base_delay = 10.0;
+        config.retry.max_delay = 1.0;
+        assert_eq!(
+            validate_config(&config),
+            vec![String::from(
+                "retry max_delay can't be less than base_delay"
+            )]
+        );
+    }
+
+    #[test]
+    fn validate_readme_config() {
+        std::env::set_var("MY_CLOUDFLARE_TOKEN", "dec0de");
+        let config = crate::config::parse_config("assets/readme-config.toml").unwrap();
+        assert!(validate_config(&config).is_empty());
+        for domain in &config.domains {
+            assert!(validate_domain(domain).is_empty());
+        }
+    }
+}
@@ -1,13 +1,16 @@
-use crate::config::{CloudflareConfig, IpType};
-use crate::core::Updates;
+use crate::config::{CloudflareConfig, CloudflareRecordSettings, IpType, RetryConfig};
+use crate::core::{ChangeLog, Context, Updates};
+use crate::retry::SendRetry;
 use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
 use std::net::IpAddr;
+use std::sync::Arc;
 
-trait CloudflareAuthorizer: fmt::Debug {
+trait CloudflareAuthorizer: fmt::Debug + Send + Sync {
     fn with_auth(&self, request_builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder;
 }
 
@@ -47,11 +50,37 @@ struct CloudflareDnsRecord {
     id: String,
     name: String,
     content: String,
+    proxied: Option<bool>,
+    ttl: Option<u32>,
+    comment: Option<String>,
+    tags: Option<Vec<String>>,
 }
 
 #[derive(Serialize, PartialEq, Clone, Debug)]
 struct CloudflareDnsRecordUpdate {
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+struct CloudflareDnsRecordCreate {
+    r#type: &'static str,
+    name: String,
+    content: String,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, PartialEq, Clone, Debug)]
@@ -79,11 +108,17 @@ struct CloudflareResultInfo {
 
 #[derive(Debug)]
 struct CloudflareClient<'a> {
+    base_url: String,
     zone_name: String,
     zone_id: String,
     records: HashSet<String>,
-    authorizer: Box<dyn CloudflareAuthorizer>,
+    settings: HashMap<String, CloudflareRecordSettings>,
+    authorizer: Arc<dyn CloudflareAuthorizer>,
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
+    create_ttl: Option<u32>,
+    dry_run: bool,
+    changes: &'a ChangeLog,
 }
 
 #[derive(Debug)]
@@ -98,6 +133,21 @@ pub enum ClErrorKind {
     ErrorResponse(&'static str, Vec<CloudflareError>),
     MissingResult(&'static str),
     UnexpectedNumberOfZones(usize),
+    MissingCredentials,
+}
+
+impl ClError {
+    /// A short name for the kind of error, used as a metrics label
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            ClErrorKind::SendHttp(..) => "send_http",
+            ClErrorKind::DecodeHttp(..) => "decode_http",
+            ClErrorKind::ErrorResponse(..) => "error_response",
+            ClErrorKind::MissingResult(_) => "missing_result",
+            ClErrorKind::UnexpectedNumberOfZones(_) => "unexpected_number_of_zones",
+            ClErrorKind::MissingCredentials => "missing_credentials",
+        }
+    }
 }
 
 impl error::Error for ClError {
@@ -131,6 +181,9 @@ impl fmt::Display for ClError {
             ClErrorKind::UnexpectedNumberOfZones(zones) => {
                 write!(f, "expected 1 zone to be returned, not {}", zones)
             }
+            ClErrorKind::MissingCredentials => {
+                write!(f, "missing either token or email + key in config")
+            }
         }
     }
 }
@@ -143,7 +196,7 @@ fn empty_to_none<P: AsRef<str>>(s: P) -> Option<P> {
     }
 }
 
-fn create_authorizer(config: &CloudflareConfig) -> Box<dyn CloudflareAuthorizer> {
+fn create_authorizer(config: &CloudflareConfig) -> Result<Box<dyn CloudflareAuthorizer>, ClError> {
     let token = config.token.as_ref().and_then(empty_to_none);
     let email = config.email.as_ref().and_then(empty_to_none);
     let key = config.key.as_ref().and_then(empty_to_none);
@@ -154,194 +207,334 @@ fn create_authorizer(config: &CloudflareConfig) -> Box<dyn CloudflareAuthorizer>
         if email.is_some() || key.is_some() {
             log::warn!(
                 "ignoring email and key fields as token is already given for zone: {}",
-                &config.zone
+                config.zone_label()
             );
         }
 
-        Box::new(BearerAuthorizer {
+        Ok(Box::new(BearerAuthorizer {
             token: token.to_string(),
-        })
+        }))
     } else if let Some((email, key)) = email.and_then(|x| key.map(|y| (x, y))) {
-        Box::new(EmailKeyAuthorizer {
+        Ok(Box::new(EmailKeyAuthorizer {
             email: email.to_string(),
             key: key.to_string(),
-        })
+        }))
     } else {
-        // If neither are provided, log an error and create a dummy authorizer
-        log::error!(
-            "missing either token or email + key in cloudflare config for zone: {}",
-            &config.zone
-        );
-
-        Box::new(BearerAuthorizer {
-            token: "".to_string(),
+        // Rather than sending requests that are bound to be rejected, fail upfront
+        Err(ClError {
+            kind: ClErrorKind::MissingCredentials,
         })
     }
 }
 
-impl CloudflareClient<'_> {
-    async fn create<'b>(
-        client: &'b reqwest::Client,
-        config: &CloudflareConfig,
-    ) -> Result<CloudflareClient<'b>, ClError> {
-        let authorizer = create_authorizer(config);
+// Fetches every page of a listing, as cloudflare paginates zones and records
+async fn paginate<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    retry: &RetryConfig,
+    authorizer: &dyn CloudflareAuthorizer,
+    url: &str,
+    query: &[(&str, &str)],
+    action: &'static str,
+) -> Result<Vec<T>, ClError> {
+    let mut done = false;
+    let mut page = 0;
+    let mut results: Vec<T> = Vec::new();
 
-        // Need to translate our zone name into an id
-        let mut request_builder: reqwest::RequestBuilder = client
-            .get("https://api.cloudflare.com/client/v4/zones")
-            .query(&[("name", &config.zone)]);
+    while !done {
+        page += 1;
+
+        debug!("grabbing page {} from {}", page, url);
+        let mut request_builder: reqwest::RequestBuilder =
+            client.get(url).query(&[("page", page)]).query(query);
 
         request_builder = authorizer.with_auth(request_builder);
 
-        let response: CloudflareResponse<Vec<CloudflareZone>> = request_builder
-            .send()
+        let response: CloudflareResponse<Vec<T>> = request_builder
+            .send_retry(retry)
             .await
             .map_err(|e| ClError {
-                kind: ClErrorKind::SendHttp("get zones", e),
+                kind: ClErrorKind::SendHttp(action, e),
             })?
             .json()
             .await
             .map_err(|e| ClError {
-                kind: ClErrorKind::DecodeHttp("get zones", e),
+                kind: ClErrorKind::DecodeHttp(action, e),
             })?;
 
         if !response.success {
-            Err(ClError {
-                kind: ClErrorKind::ErrorResponse("zones", response.errors.clone()),
-            })
-        } else if let Some(zone) = response.result {
-            if zone.len() != 1 {
-                return Err(ClError {
-                    kind: ClErrorKind::UnexpectedNumberOfZones(zone.len()),
-                });
+            return Err(ClError {
+                kind: ClErrorKind::ErrorResponse(action, response.errors),
+            });
+        } else if let Some(result) = response.result {
+            results.extend(result);
+
+            if let Some(info) = response.result_info {
+                done = info.total_pages <= page;
+            } else {
+                done = true;
+                warn!(
+                    "did not receive a result info page for {}, assuming no more results",
+                    url
+                );
             }
+        } else {
+            return Err(ClError {
+                kind: ClErrorKind::MissingResult(action),
+            });
+        }
+    }
 
-            let zone_id = zone[0].id.clone();
+    Ok(results)
+}
 
-            Ok(CloudflareClient {
-                zone_name: config.zone.clone(),
-                zone_id,
-                records: config.records.iter().cloned().collect(),
-                client,
-                authorizer,
-            })
-        } else {
-            Err(ClError {
-                kind: ClErrorKind::MissingResult("zones"),
-            })
+/// Assigns each record to the most specific zone that contains it. Zones without any records are
+/// dropped and records without a zone are returned separately.
+fn group_by_zone(
+    mut zones: Vec<CloudflareZone>,
+    records: &[String],
+) -> (Vec<(CloudflareZone, HashSet<String>)>, Vec<String>) {
+    zones.sort_by_key(|x| std::cmp::Reverse(x.name.len()));
+
+    let mut unmatched = records.iter().cloned().collect::<HashSet<String>>();
+    let mut grouped = Vec::new();
+    for zone in zones {
+        let suffix = format!(".{}", zone.name);
+        let zone_records = unmatched
+            .iter()
+            .filter(|x| **x == zone.name || x.ends_with(&suffix))
+            .cloned()
+            .collect::<HashSet<String>>();
+
+        if !zone_records.is_empty() {
+            unmatched.retain(|x| !zone_records.contains(x));
+            grouped.push((zone, zone_records));
         }
     }
 
-    // Grab all the sub domains in the zone, but since there can be many of them, cloudflare
-    // paginates the results.
-    async fn paginate_domains(&self, ip_type: IpType) -> Result<Vec<CloudflareDnsRecord>, ClError> {
-        let mut done = false;
-        let mut page = 0;
-        let mut dns_records: Vec<CloudflareDnsRecord> = Vec::new();
+    let mut unmatched = unmatched.into_iter().collect::<Vec<String>>();
+    unmatched.sort();
+    (grouped, unmatched)
+}
 
-        let record_url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-            self.zone_id
-        );
+fn describe_drift(drift: &[&str]) -> String {
+    if drift.is_empty() {
+        String::new()
+    } else {
+        format!(" and corrected {}", drift.join(", "))
+    }
+}
 
-        while !done {
-            page += 1;
-
-            debug!("grabbing page {} from {}", page, record_url);
-            let mut request_builder: reqwest::RequestBuilder = self
-                .client
-                .get(&record_url)
-                .query(&[("page", page)])
-                .query(&[("type", ip_type.record_type())]);
-
-            request_builder = self.authorizer.with_auth(request_builder);
-
-            let response: CloudflareResponse<Vec<CloudflareDnsRecord>> = request_builder
-                .send()
-                .await
-                .map_err(|e| ClError {
-                    kind: ClErrorKind::SendHttp("get records", e),
-                })?
-                .json()
-                .await
-                .map_err(|e| ClError {
-                    kind: ClErrorKind::DecodeHttp("get records", e),
-                })?;
-
-            if !response.success {
-                return Err(ClError {
-                    kind: ClErrorKind::ErrorResponse("get records", response.errors.clone()),
-                });
-            } else if let Some(records) = response.result {
-                dns_records.extend(records);
-
-                if let Some(info) = response.result_info {
-                    done = info.total_pages <= page;
-                } else {
-                    done = true;
-                    warn!(
-                        "did not receive a result info page for {}, assuming no more results",
-                        self.zone_name
-                    );
+/// The names of the configured settings that the record doesn't match
+fn settings_drift(
+    record: &CloudflareDnsRecord,
+    settings: &CloudflareRecordSettings,
+) -> Vec<&'static str> {
+    let mut drift = Vec::new();
+    if settings.proxied.is_some_and(|x| record.proxied != Some(x)) {
+        drift.push("proxied");
+    }
+
+    if settings.ttl.is_some_and(|x| record.ttl != Some(x)) {
+        drift.push("ttl");
+    }
+
+    // Cloudflare reports a record without a comment as null
+    let comment = record.comment.as_deref().unwrap_or("");
+    if settings.comment.as_ref().is_some_and(|x| x != comment) {
+        drift.push("comment");
+    }
+
+    // Tags are unordered
+    if let Some(tags) = &settings.tags {
+        let mut expected = tags.clone();
+        let mut actual = record.tags.clone().unwrap_or_default();
+        expected.sort();
+        actual.sort();
+        if expected != actual {
+            drift.push("tags");
+        }
+    }
+
+    drift
+}
+
+impl CloudflareClient<'_> {
+    /// Creates a client for each zone with configured records. The zone is either given by id,
+    /// looked up by name, or when neither is configured, discovered among all the zones the
+    /// credentials can see. Records that don't belong to any of these zones are returned too.
+    async fn create<'b>(
+        ctx: &Context<'b>,
+        config: &CloudflareConfig,
+    ) -> Result<(Vec<CloudflareClient<'b>>, Vec<String>), ClError> {
+        let client = ctx.http;
+        let authorizer: Arc<dyn CloudflareAuthorizer> = Arc::from(create_authorizer(config)?);
+        let base_url = config.base_url.trim_end_matches('/').to_string();
+        let zones_url = format!("{}/zones", base_url);
+
+        let (grouped, unmatched) = match (&config.zone_id, &config.zone) {
+            (Some(zone_id), zone) => {
+                // Scoped tokens may not be able to read zones, so trust the given id
+                let zone = CloudflareZone {
+                    id: zone_id.clone(),
+                    name: zone.clone().unwrap_or_else(|| zone_id.clone()),
+                };
+                (
+                    vec![(zone, config.records.iter().cloned().collect())],
+                    Vec::new(),
+                )
+            }
+            (None, Some(zone)) => {
+                // Need to translate our zone name into an id
+                let mut zones: Vec<CloudflareZone> = paginate(
+                    client,
+                    ctx.retry,
+                    authorizer.as_ref(),
+                    &zones_url,
+                    &[("name", zone)],
+                    "get zones",
+                )
+                .await?;
+
+                if zones.len() != 1 {
+                    return Err(ClError {
+                        kind: ClErrorKind::UnexpectedNumberOfZones(zones.len()),
+                    });
                 }
-            } else {
-                return Err(ClError {
-                    kind: ClErrorKind::MissingResult("get records"),
-                });
+
+                let zone = zones.remove(0);
+                (
+                    vec![(zone, config.records.iter().cloned().collect())],
+                    Vec::new(),
+                )
             }
-        }
+            (None, None) => {
+                let zones = paginate(
+                    client,
+                    ctx.retry,
+                    authorizer.as_ref(),
+                    &zones_url,
+                    &[],
+                    "get zones",
+                )
+                .await?;
+                group_by_zone(zones, &config.records)
+            }
+        };
 
-        Ok(dns_records)
+        let clients = grouped
+            .into_iter()
+            .map(|(zone, records)| CloudflareClient {
+                base_url: base_url.clone(),
+                zone_name: zone.name,
+                zone_id: zone.id,
+                settings: records
+                    .iter()
+                    .map(|x| (x.clone(), config.record_settings(x)))
+                    .collect(),
+                records,
+                client,
+                retry: ctx.retry,
+                authorizer: Arc::clone(&authorizer),
+                create_ttl: Some(config.create_ttl).filter(|_| config.create_missing),
+                dry_run: ctx.dry_run,
+                changes: ctx.changes,
+            })
+            .collect();
+
+        Ok((clients, unmatched))
     }
 
-    // Logs the domains found in the config but not in cloudflare
-    fn log_missing_domains(&self, remote_domains: &[CloudflareDnsRecord]) -> usize {
-        let actual = remote_domains
+    // Grab all the sub domains in the zone, but since there can be many of them, cloudflare
+    // paginates the results.
+    async fn paginate_domains(&self, ip_type: IpType) -> Result<Vec<CloudflareDnsRecord>, ClError> {
+        let record_url = format!("{}/zones/{}/dns_records", self.base_url, self.zone_id);
+        paginate(
+            self.client,
+            self.retry,
+            self.authorizer.as_ref(),
+            &record_url,
+            &[("type", ip_type.record_type())],
+            "get records",
+        )
+        .await
+    }
+
+    fn remote_names(remote_domains: &[CloudflareDnsRecord]) -> HashSet<String> {
+        remote_domains
             .iter()
             .map(|x| &x.name)
             .cloned()
-            .collect::<HashSet<String>>();
-        crate::core::log_missing_domains(&self.records, &actual, "cloudflare", &self.zone_name)
+            .collect::<HashSet<String>>()
+    }
+
+    // Logs the domains found in the config but not in cloudflare
+    fn log_missing_domains(&self, remote_domains: &[CloudflareDnsRecord]) -> usize {
+        let actual = Self::remote_names(remote_domains);
+        let missing =
+            crate::core::log_missing_domains(&self.records, &actual, "cloudflare", &self.zone_name);
+        for name in &missing {
+            self.changes.missing(name);
+        }
+        missing.len()
+    }
+
+    fn missing_domains(&self, remote_domains: &[CloudflareDnsRecord]) -> Vec<String> {
+        crate::core::missing_records(&self.records, &Self::remote_names(remote_domains))
     }
 
     async fn update(&self, addr: IpAddr) -> Result<Updates, ClError> {
         let mut dns_records = self.paginate_domains(IpType::from(addr)).await?;
-        let missing = self.log_missing_domains(&dns_records) as i32;
         let mut current = 0;
         let mut updated = 0;
+        let mut missing = 0;
+
+        if let Some(ttl) = self.create_ttl {
+            for name in self.missing_domains(&dns_records) {
+                updated += 1;
+                if self.dry_run {
+                    self.changes.plan(&name, &self.zone_name, "", addr);
+                } else {
+                    self.create_record(&name, addr, ttl).await?;
+                    self.changes.push(&name, &self.zone_name, "", addr);
+                    info!(
+                        "{} from zone {} created with {}",
+                        name, self.zone_name, addr
+                    )
+                }
+            }
+        } else {
+            missing = self.log_missing_domains(&dns_records) as i32;
+        }
 
         let recs = dns_records
             .iter_mut()
             .filter(|x| self.records.contains(&x.name));
 
         for record in recs {
+            let settings = self.record_settings(&record.name);
+            let drift = settings_drift(record, &settings);
             match record.content.parse::<IpAddr>() {
-                Ok(ip) => {
-                    if ip != addr {
-                        updated += 1;
-                        self.update_record(record, addr).await?;
-
-                        info!(
-                            "{} from zone {} updated from {} to {}",
-                            record.name, self.zone_name, record.content, addr
-                        )
-                    } else {
-                        current += 1;
-                        debug!(
-                            "{} from zone {} is already current",
-                            record.name, self.zone_name
-                        )
-                    }
+                Ok(ip) if ip == addr && drift.is_empty() => {
+                    current += 1;
+                    self.changes.current(&record.name);
+                    debug!(
+                        "{} from zone {} is already current",
+                        record.name, self.zone_name
+                    )
+                }
+                Ok(ip) if ip == addr => {
+                    // Only the settings are corrected, so the address is still current
+                    current += 1;
+                    self.apply(record, addr, &settings, &drift).await?;
+                }
+                Ok(_) => {
+                    updated += 1;
+                    self.apply(record, addr, &settings, &drift).await?;
                 }
                 Err(ref e) => {
                     updated += 1;
                     warn!("could not parse domain {} address {} -- will replace it. Original error: {}", record.name, record.content, e);
-                    self.update_record(record, addr).await?;
-
-                    info!(
-                        "{} from zone {} update from {} to {}",
-                        record.name, self.zone_name, record.content, addr
-                    )
+                    self.apply(record, addr, &settings, &drift).await?;
                 }
             }
         }
@@ -353,14 +546,69 @@ impl CloudflareClient<'_> {
         })
     }
 
+    /// Points the record at the address and corrects any drifted settings, or on a dry run notes
+    /// that it would. A record whose address was already right is noted as corrected rather than
+    /// changed, so that it doesn't trigger hooks and notifications meant for new addresses.
+    async fn apply(
+        &self,
+        record: &CloudflareDnsRecord,
+        addr: IpAddr,
+        settings: &CloudflareRecordSettings,
+        drift: &[&str],
+    ) -> Result<(), ClError> {
+        let address_current = record.content.parse::<IpAddr>().ok() == Some(addr);
+        if self.dry_run {
+            if address_current {
+                self.changes.plan_corrected(&record.name, addr, drift);
+            } else {
+                self.changes.plan_settings(
+                    &record.name,
+                    &self.zone_name,
+                    &record.content,
+                    addr,
+                    drift,
+                );
+            }
+            return Ok(());
+        }
+
+        self.update_record(record, addr, settings).await?;
+        if address_current {
+            self.changes.corrected(&record.name);
+            info!(
+                "{} from zone {} corrected {}",
+                record.name,
+                self.zone_name,
+                drift.join(", ")
+            )
+        } else {
+            self.changes
+                .push(&record.name, &self.zone_name, &record.content, addr);
+            info!(
+                "{} from zone {} updated from {} to {}{}",
+                record.name,
+                self.zone_name,
+                record.content,
+                addr,
+                describe_drift(drift)
+            )
+        }
+        Ok(())
+    }
+
+    fn record_settings(&self, name: &str) -> CloudflareRecordSettings {
+        self.settings.get(name).cloned().unwrap_or_default()
+    }
+
     async fn update_record(
         &self,
         record: &CloudflareDnsRecord,
         addr: IpAddr,
+        settings: &CloudflareRecordSettings,
     ) -> Result<(), ClError> {
         let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-            self.zone_id, record.id
+            "{}/zones/{}/dns_records/{}",
+            self.base_url, self.zone_id, record.id
         );
 
         debug!(
@@ -368,8 +616,13 @@ impl CloudflareClient<'_> {
             record.name, self.zone_name, record.content, addr, &url
         );
 
+        // The configured settings are sent as a whole, so drift is corrected in the same request
         let update = CloudflareDnsRecordUpdate {
             content: addr.to_string(),
+            proxied: settings.proxied,
+            ttl: settings.ttl,
+            comment: settings.comment.clone(),
+            tags: settings.tags.clone(),
         };
 
         let mut request_builder: reqwest::RequestBuilder = self.client.patch(&url);
@@ -377,7 +630,7 @@ impl CloudflareClient<'_> {
 
         let response: CloudflareResponse<CloudflareDnsRecord> = request_builder
             .json(&update)
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| ClError {
                 kind: ClErrorKind::SendHttp("update dns", e),
@@ -396,10 +649,72 @@ impl CloudflareClient<'_> {
             Ok(())
         }
     }
+
+    async fn create_record(&self, name: &str, addr: IpAddr, ttl: u32) -> Result<(), ClError> {
+        let url = format!("{}/zones/{}/dns_records", self.base_url, self.zone_id);
+
+        debug!(
+            "{} from zone {} creating with {}: {}",
+            name, self.zone_name, addr, &url
+        );
+
+        let settings = self.record_settings(name);
+        let create = CloudflareDnsRecordCreate {
+            r#type: IpType::from(addr).record_type(),
+            name: name.to_string(),
+            content: addr.to_string(),
+            ttl: settings.ttl.unwrap_or(ttl),
+            proxied: settings.proxied,
+            comment: settings.comment,
+            tags: settings.tags,
+        };
+
+        let mut request_builder: reqwest::RequestBuilder = self.client.post(&url);
+        request_builder = self.authorizer.with_auth(request_builder);
+
+        let response: CloudflareResponse<CloudflareDnsRecord> = request_builder
+            .json(&create)
+            .send_retry_non_idempotent(self.retry)
+            .await
+            .map_err(|e| ClError {
+                kind: ClErrorKind::SendHttp("create dns", e),
+            })?
+            .json()
+            .await
+            .map_err(|e| ClError {
+                kind: ClErrorKind::DecodeHttp("create dns", e),
+            })?;
+
+        if !response.success {
+            Err(ClError {
+                kind: ClErrorKind::ErrorResponse("create dns", response.errors),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Confirms that cloudflare accepts the credentials and returns the configured records that don't
+/// exist in the zone
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &CloudflareConfig,
+    ip_type: IpType,
+) -> Result<Vec<String>, ClError> {
+    let (clients, mut missing) = CloudflareClient::create(ctx, config).await?;
+    for client in clients {
+        let records = client.paginate_domains(ip_type).await?;
+        missing.extend(client.missing_domains(&records));
+    }
+    missing.sort();
+    Ok(missing)
 }
 
 /// Updating cloudflare domain works as follows:
-///  1. Send GET to translate the zone (example.com) to cloudflare's id
+///  1. Send GET to translate the zone (example.com) to cloudflare's id. This is skipped when the
+///     zone id is configured, and when no zone is configured all zones are fetched instead so
+///     that each record can be matched to its zone
 ///  2. Send GET to find all the domains under the zone and their ids
 ///    - Cloudflare paginates the response to handle many subdomains
 ///    - It is possible to query for individual domains but as long as more
@@ -407,19 +722,40 @@ impl CloudflareClient<'_> {
 ///  3. Each desired domain in the config is checked to ensure that it is set to our address. In
 ///     this way cloudflare is our cache (to guard against nefarious users updating out of band)
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &CloudflareConfig,
     addr: IpAddr,
 ) -> Result<Updates, ClError> {
-    CloudflareClient::create(client, config)
-        .await?
-        .update(addr)
-        .await
+    let (clients, unmatched) = CloudflareClient::create(ctx, config).await?;
+    if !unmatched.is_empty() {
+        warn!(
+            "records not found in any cloudflare zone: {}",
+            unmatched.join(", ")
+        );
+    }
+    for name in &unmatched {
+        ctx.changes.missing(name);
+    }
+
+    let mut summary = Updates {
+        missing: unmatched.len() as i32,
+        ..Updates::default()
+    };
+
+    for client in clients {
+        summary += client.update(addr).await?;
+    }
+
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DnsTransport;
+    use crate::core::RecordStatus;
+    use crate::dns::DnsResolver;
+    use std::net::Ipv4Addr;
 
     #[test]
     fn deserialize_cloudflare_error() {
@@ -478,6 +814,10 @@ mod tests {
                     id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
                     name: String::from("example.com"),
                     content: String::from("198.51.100.4"),
+                    proxied: None,
+                    ttl: None,
+                    comment: None,
+                    tags: None,
                 }),
                 result_info: None,
                 success: true,
@@ -499,6 +839,10 @@ mod tests {
                     id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
                     name: String::from("example.com"),
                     content: String::from("2600:1406:bc00:53::b81e:94ce"),
+                    proxied: None,
+                    ttl: None,
+                    comment: None,
+                    tags: None,
                 }),
                 result_info: None,
                 success: true,
@@ -506,4 +850,430 @@ mod tests {
             }
         );
     }
+
+    macro_rules! cloudflare_rouille_server {
+        () => {{
+            cloudflare_rouille_server!(std::sync::Arc::new(std::sync::Mutex::new(Vec::new())))
+        }};
+        ($patches:expr) => {{
+            use rouille::Response;
+            use rouille::Server;
+            use std::io::Read;
+
+            // The bodies of the PATCH requests, for checking the settings that were sent
+            let patches: std::sync::Arc<std::sync::Mutex<Vec<String>>> = $patches.clone();
+            let server = Server::new("localhost:0", move |request| {
+                if request.method() == "PATCH" {
+                    let mut body = String::new();
+                    request.data().unwrap().read_to_string(&mut body).unwrap();
+                    patches.lock().unwrap().push(body);
+                }
+
+                let json = |data: &[u8]| Response::from_data("application/json", data.to_vec());
+                let zone = request.get_param("name");
+                let page = request.get_param("page");
+                match (request.method(), request.url().as_str()) {
+                    ("GET", "/client/v4/zones") => match zone.as_deref() {
+                        Some("example.com") => {
+                            json(include_bytes!("../assets/cloudflare-zone-response.json"))
+                        }
+                        Some("empty.com") => {
+                            json(br#"{"result": [], "success": true, "errors": []}"#)
+                        }
+                        None => json(include_bytes!("../assets/cloudflare-zones-response.json")),
+                        _ => json(include_bytes!("../assets/cloudflare-error.json"))
+                            .with_status_code(400),
+                    },
+                    ("GET", "/client/v4/zones/aaaabbbb/dns_records") => match page.as_deref() {
+                        Some("1") => {
+                            json(include_bytes!("../assets/cloudflare-records-page-1.json"))
+                        }
+                        Some("2") => {
+                            json(include_bytes!("../assets/cloudflare-records-page-2.json"))
+                        }
+                        _ => Response::empty_404(),
+                    },
+                    ("GET", "/client/v4/zones/ccccdddd/dns_records") => json(
+                        br#"{"result": [{"id": "1", "name": "n.example2.com", "content": "2.2.2.2"}], "success": true, "errors": []}"#,
+                    ),
+                    ("POST", "/client/v4/zones/aaaabbbb/dns_records")
+                    | (
+                        "PATCH",
+                        "/client/v4/zones/aaaabbbb/dns_records/372e67954025e0ba6aaa6d586b9e0b59",
+                    )
+                    | (
+                        "PATCH",
+                        "/client/v4/zones/aaaabbbb/dns_records/372e67954025e0ba6aaa6d586b9e0b5b",
+                    )
+                    | (
+                        "PATCH",
+                        "/client/v4/zones/aaaabbbb/dns_records/372e67954025e0ba6aaa6d586b9e0b5a",
+                    ) => json(include_bytes!(
+                        "../assets/cloudflare-update-a-response.json"
+                    )),
+                    _ => Response::empty_404(),
+                }
+            })
+            .unwrap();
+
+            let (tx, rx) = std::sync::mpsc::sync_channel(1);
+            let addr = server.server_addr().clone();
+            std::thread::spawn(move || {
+                while let Err(_) = rx.try_recv() {
+                    server.poll();
+                    std::thread::sleep(std::time::Duration::from_millis(50))
+                }
+            });
+            (tx, addr)
+        }};
+    }
+
+    fn cloudflare_config(base_url: String, zone: &str, records: &[&str]) -> CloudflareConfig {
+        CloudflareConfig {
+            base_url,
+            email: None,
+            key: None,
+            token: Some(String::from("dec0de")),
+            zone: Some(String::from(zone)),
+            zone_id: None,
+            records: records.iter().map(|x| String::from(*x)).collect(),
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 1,
+            proxied: None,
+            ttl: None,
+            comment: None,
+            tags: None,
+            overrides: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_update() {
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = cloudflare_config(
+            format!("http://{}/client/v4/", addr),
+            "example.com",
+            &[
+                "n.example.com",
+                "n2.example.com",
+                "n3.example.com",
+                "n4.example.com",
+            ],
+        );
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 2,
+                missing: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_create_missing() {
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = CloudflareConfig {
+            create_missing: true,
+            ..cloudflare_config(
+                format!("http://{}/client/v4", addr),
+                "example.com",
+                &["n2.example.com", "n4.example.com"],
+            )
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 1,
+                missing: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_error_response() {
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = cloudflare_config(
+            format!("http://{}/client/v4", addr),
+            "example2.com",
+            &["n.example2.com"],
+        );
+
+        let err = update_domains(&ctx, &config, new_ip).await.unwrap_err();
+        tx.send(()).unwrap();
+
+        assert!(err
+            .to_string()
+            .contains("1003: Invalid or missing zone id."));
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_unexpected_zones() {
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = cloudflare_config(
+            format!("http://{}/client/v4", addr),
+            "empty.com",
+            &["n.empty.com"],
+        );
+
+        let err = update_domains(&ctx, &config, new_ip).await.unwrap_err();
+        tx.send(()).unwrap();
+
+        assert!(matches!(err.kind, ClErrorKind::UnexpectedNumberOfZones(0)));
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_zone_id() {
+        // The zone lookup is rejected, so the test fails unless it is skipped
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = CloudflareConfig {
+            zone: None,
+            zone_id: Some(String::from("aaaabbbb")),
+            ..cloudflare_config(
+                format!("http://{}/client/v4", addr),
+                "",
+                &["n.example.com", "n2.example.com"],
+            )
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 1,
+                missing: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_discover_zones() {
+        let (tx, addr) = cloudflare_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let changes = ChangeLog::default();
+        let ctx = Context {
+            changes: &changes,
+            ..Context::for_test(&http_client, &resolver)
+        };
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = CloudflareConfig {
+            zone: None,
+            ..cloudflare_config(
+                format!("http://{}/client/v4", addr),
+                "",
+                &["n.example.com", "n.example2.com", "n.example3.com"],
+            )
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 1,
+                missing: 1,
+            }
+        );
+
+        // The record outside of every zone is reported alongside the others
+        assert!(changes
+            .take_unchanged()
+            .contains(&(String::from("n.example3.com"), RecordStatus::Missing)));
+    }
+
+    #[test]
+    fn test_group_by_zone() {
+        let zone = |name: &str| CloudflareZone {
+            id: format!("{}-id", name),
+            name: String::from(name),
+        };
+        let records = [
+            "example.com",
+            "a.example.com",
+            "b.sub.example.com",
+            "other.com",
+        ]
+        .iter()
+        .map(|x| String::from(*x))
+        .collect::<Vec<String>>();
+
+        let (grouped, unmatched) =
+            group_by_zone(vec![zone("example.com"), zone("sub.example.com")], &records);
+
+        let names = |x: &[&str]| x.iter().map(|x| String::from(*x)).collect::<HashSet<_>>();
+        assert_eq!(
+            grouped,
+            vec![
+                (zone("sub.example.com"), names(&["b.sub.example.com"])),
+                (
+                    zone("example.com"),
+                    names(&["example.com", "a.example.com"])
+                ),
+            ]
+        );
+        assert_eq!(unmatched, vec![String::from("other.com")]);
+    }
+
+    #[tokio::test]
+    async fn test_cloudflare_settings_drift() {
+        let patches = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (tx, addr) = cloudflare_rouille_server!(patches);
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let changes = ChangeLog::default();
+        let ctx = Context {
+            changes: &changes,
+            ..Context::for_test(&http_client, &resolver)
+        };
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            String::from("n2.example.com"),
+            CloudflareRecordSettings {
+                proxied: Some(true),
+                ..CloudflareRecordSettings::default()
+            },
+        );
+
+        // n2 has the right address but should be proxied
+        let config = CloudflareConfig {
+            proxied: Some(false),
+            ttl: Some(1),
+            overrides,
+            ..cloudflare_config(
+                format!("http://{}/client/v4", addr),
+                "example.com",
+                &["n2.example.com"],
+            )
+        };
+
+        // The address didn't change, so the correction is neither counted nor noted as an update
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 0,
+                missing: 0,
+            }
+        );
+        assert!(changes.take().is_empty());
+        assert_eq!(
+            changes.take_unchanged(),
+            vec![(String::from("n2.example.com"), RecordStatus::Corrected)]
+        );
+
+        let sent = patches.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&sent[0]).unwrap();
+        assert_eq!(body["content"], "2.2.2.2");
+        assert_eq!(body["proxied"], true);
+        assert_eq!(body["ttl"], 1);
+
+        // Without the override, the record matches the domain's settings
+        let config = CloudflareConfig {
+            overrides: HashMap::new(),
+            ..config
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 0,
+                missing: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_settings_drift() {
+        let record = CloudflareDnsRecord {
+            id: String::from("1"),
+            name: String::from("n.example.com"),
+            content: String::from("2.2.2.2"),
+            proxied: Some(false),
+            ttl: Some(300),
+            comment: None,
+            tags: Some(vec![String::from("b"), String::from("a")]),
+        };
+
+        assert!(settings_drift(&record, &CloudflareRecordSettings::default()).is_empty());
+
+        let settings = CloudflareRecordSettings {
+            proxied: Some(false),
+            ttl: Some(300),
+            comment: Some(String::new()),
+            tags: Some(vec![String::from("a"), String::from("b")]),
+        };
+        assert!(settings_drift(&record, &settings).is_empty());
+
+        let settings = CloudflareRecordSettings {
+            proxied: Some(true),
+            ttl: Some(1),
+            comment: Some(String::from("dness")),
+            tags: Some(vec![String::from("a")]),
+        };
+        assert_eq!(
+            settings_drift(&record, &settings),
+            vec!["proxied", "ttl", "comment", "tags"]
+        );
+    }
 }
@@ -1,12 +1,13 @@
 use handlebars::{Handlebars, RenderError, TemplateError};
 use log::LevelFilter;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::File;
 use std::io::Error as IoError;
 use std::io::Read;
-use std::net::IpAddr;
-use std::path::Path;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::{collections::HashMap, error};
 
 #[derive(Debug)]
@@ -51,8 +52,47 @@ impl fmt::Display for ConfigError {
 #[derive(Deserialize, Clone, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct DnsConfig {
-    #[serde(default = "default_resolver")]
-    pub ip_resolver: String,
+    #[serde(default = "default_resolver", deserialize_with = "one_or_many")]
+    pub ip_resolver: Vec<String>,
+
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+
+    /// How many domains are updated at once
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    /// Limits on how many domains of a provider are updated at once, keyed by the provider's type
+    #[serde(default)]
+    pub provider_concurrency: BTreeMap<String, usize>,
+
+    pub state_file: Option<PathBuf>,
+
+    /// Commands run after each changed record, and when an update fails
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+
+    /// Seconds a hook may run before it's killed
+    #[serde(default = "default_hook_timeout")]
+    pub hook_timeout: u64,
+
+    #[serde(default)]
+    pub resolvers: ResolversConfig,
+
+    #[serde(default)]
+    pub notify: Vec<NotifyConfig>,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    #[serde(default)]
+    pub lookup: LookupConfig,
+
+    #[serde(default)]
+    pub http: HttpConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
 
     #[serde(default)]
     pub log: LogConfig,
@@ -61,20 +101,457 @@ pub struct DnsConfig {
     pub domains: Vec<DomainConfig>,
 }
 
-fn default_resolver() -> String {
-    String::from("opendns")
+fn default_resolver() -> Vec<String> {
+    vec![String::from("opendns")]
+}
+
+/// Accepts either a single resolver name or an ordered list of them
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(x) => vec![x],
+        OneOrMany::Many(x) => x,
+    })
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+fn default_max_concurrency() -> usize {
+    4
+}
+
+fn default_hook_timeout() -> u64 {
+    60
 }
 
 impl Default for DnsConfig {
     fn default() -> Self {
         DnsConfig {
             ip_resolver: default_resolver(),
+            interval: default_interval(),
+            max_concurrency: default_max_concurrency(),
+            provider_concurrency: BTreeMap::new(),
+            state_file: None,
+            on_update: None,
+            on_failure: None,
+            hook_timeout: default_hook_timeout(),
+            resolvers: Default::default(),
+            notify: Default::default(),
+            metrics: Default::default(),
+            lookup: Default::default(),
+            http: Default::default(),
+            retry: Default::default(),
             log: Default::default(),
             domains: Default::default(),
         }
     }
 }
 
+/// Settings for the WAN IP resolvers that need more than their name in `ip_resolver`
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ResolversConfig {
+    #[serde(default)]
+    pub strategy: ResolverStrategy,
+
+    /// Number of resolvers that must agree under the quorum strategy. Defaults to a majority.
+    pub quorum: Option<usize>,
+
+    /// How the dns based resolvers (opendns and cloudflare-dns) send their queries
+    #[serde(default)]
+    pub transport: DnsTransport,
+
+    pub interface: Option<InterfaceResolverConfig>,
+    pub http: Option<HttpResolverConfig>,
+    pub stun: Option<StunResolverConfig>,
+    pub upnp: Option<UpnpResolverConfig>,
+    pub natpmp: Option<NatPmpResolverConfig>,
+}
+
+/// How the answers of multiple WAN IP resolvers are combined
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ResolverStrategy {
+    /// Try each resolver in order until one answers
+    #[default]
+    #[serde(rename = "first-success")]
+    FirstSuccess,
+
+    /// Query every resolver concurrently and require enough of them to agree
+    #[serde(rename = "quorum")]
+    Quorum,
+}
+
+/// How dns queries are sent
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsTransport {
+    /// Unencrypted, on port 53
+    #[default]
+    Udp,
+
+    /// DNS over TLS, on port 853
+    Tls,
+
+    /// DNS over HTTPS, on port 443
+    Https,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HttpResolverConfig {
+    pub v4_url: Option<String>,
+    pub v6_url: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub regex: Option<String>,
+    pub json_pointer: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StunResolverConfig {
+    /// STUN servers as "host:port", tried in order
+    #[serde(default = "default_stun_servers")]
+    pub servers: Vec<String>,
+}
+
+fn default_stun_servers() -> Vec<String> {
+    vec![
+        String::from("stun.l.google.com:19302"),
+        String::from("stun.cloudflare.com:3478"),
+    ]
+}
+
+impl Default for StunResolverConfig {
+    fn default() -> Self {
+        StunResolverConfig {
+            servers: default_stun_servers(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UpnpResolverConfig {
+    /// The url of the router's device description, which skips SSDP discovery
+    pub location: Option<String>,
+}
+
+/// Settings for the NAT-PMP resolver. PCP isn't supported, so gateways that only speak PCP can't be
+/// asked.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NatPmpResolverConfig {
+    /// The router's address, defaulting to the gateway of the default route
+    pub gateway: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceResolverConfig {
+    pub name: String,
+    pub prefer: Option<Ipv6Preference>,
+}
+
+/// Which of an interface's stable IPv6 addresses to publish when it has both kinds
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ipv6Preference {
+    /// Derived from the MAC address
+    #[serde(rename = "eui64")]
+    Eui64,
+
+    /// Stable but opaque (RFC 7217)
+    #[serde(rename = "stable-privacy")]
+    StablePrivacy,
+}
+
+/// Where the prometheus metrics are exposed
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// The address that metrics are served from in daemon mode, eg: "127.0.0.1:9184"
+    pub listen: Option<String>,
+
+    /// A file that metrics are written to after every update cycle, for node_exporter's textfile
+    /// collector
+    pub textfile: Option<PathBuf>,
+}
+
+/// How the providers that compare records over dns (he.net, Namecheap, No-IP, and Dynu) look up
+/// the current address of a record
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LookupConfig {
+    /// How records, and the name servers of their zones, are looked up with Cloudflare's resolver.
+    /// The authoritative name servers are always asked over udp.
+    #[serde(default)]
+    pub transport: DnsTransport,
+
+    /// Ask the zone's authoritative name servers instead of a caching public resolver
+    #[serde(default)]
+    pub authoritative: bool,
+
+    /// After records are updated, wait for the authoritative name servers to answer with the new
+    /// address
+    #[serde(default)]
+    pub verify: bool,
+
+    /// Seconds to wait for updated records to converge before failing the update
+    #[serde(default = "default_verify_timeout")]
+    pub verify_timeout: u64,
+
+    /// Seconds to wait between asking whether updated records have converged
+    #[serde(default = "default_verify_interval")]
+    pub verify_interval: u64,
+}
+
+fn default_verify_timeout() -> u64 {
+    60
+}
+
+fn default_verify_interval() -> u64 {
+    5
+}
+
+impl Default for LookupConfig {
+    fn default() -> Self {
+        LookupConfig {
+            transport: DnsTransport::default(),
+            authoritative: false,
+            verify: false,
+            verify_timeout: default_verify_timeout(),
+            verify_interval: default_verify_interval(),
+        }
+    }
+}
+
+/// The http client shared by the providers and the http based WAN IP resolvers
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Seconds allowed to establish a connection
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+
+    /// Seconds allowed for a request, from connecting to reading the whole response
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u64,
+
+    /// An http, https, or socks5 proxy url that all requests are sent through
+    pub proxy: Option<String>,
+
+    /// The local address that connections are made from
+    pub local_address: Option<IpAddr>,
+
+    /// The network interface that connections are bound to (Linux only)
+    pub interface: Option<String>,
+
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+
+    /// PEM files of certificate authorities that are trusted in addition to the built in ones
+    #[serde(default)]
+    pub ca_certs: Vec<PathBuf>,
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_http_timeout() -> u64 {
+    30
+}
+
+fn default_user_agent() -> String {
+    format!("dness/{}", env!("CARGO_PKG_VERSION"))
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: default_connect_timeout(),
+            timeout: default_http_timeout(),
+            proxy: None,
+            local_address: None,
+            interface: None,
+            user_agent: default_user_agent(),
+            ca_certs: Vec::new(),
+        }
+    }
+}
+
+/// How requests that failed for a reason that is likely to pass are retried
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    /// How many times a request is sent in total, so 1 disables retries
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+
+    /// Seconds waited before the first retry, which doubles with each retry after
+    #[serde(default = "default_retry_base_delay")]
+    pub base_delay: f64,
+
+    /// The most seconds waited before a retry, including those asked for by a Retry-After header
+    #[serde(default = "default_retry_max_delay")]
+    pub max_delay: f64,
+
+    /// The http statuses that are retried, in addition to connection errors and timeouts. Only
+    /// these are, not every 5xx status.
+    #[serde(default = "default_retry_statuses")]
+    pub statuses: Vec<u16>,
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_delay() -> f64 {
+    1.0
+}
+
+fn default_retry_max_delay() -> f64 {
+    30.0
+}
+
+fn default_retry_statuses() -> Vec<u16> {
+    vec![429, 500, 502, 503, 504]
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: default_retry_attempts(),
+            base_delay: default_retry_base_delay(),
+            max_delay: default_retry_max_delay(),
+            statuses: default_retry_statuses(),
+        }
+    }
+}
+
+/// A destination that is told when records change or an update fails
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyConfig {
+    Webhook(WebhookConfig),
+    Ntfy(NtfyConfig),
+    Gotify(GotifyConfig),
+    Smtp(SmtpConfig),
+}
+
+impl NotifyConfig {
+    pub fn on(&self) -> NotifyOn {
+        match self {
+            NotifyConfig::Webhook(c) => c.on,
+            NotifyConfig::Ntfy(c) => c.on,
+            NotifyConfig::Gotify(c) => c.on,
+            NotifyConfig::Smtp(c) => c.on,
+        }
+    }
+}
+
+/// Which events a notification backend is sent
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    Change,
+    Failure,
+    #[default]
+    Both,
+}
+
+impl NotifyOn {
+    pub fn change(self) -> bool {
+        self != NotifyOn::Failure
+    }
+
+    pub fn failure(self) -> bool {
+        self != NotifyOn::Change
+    }
+}
+
+/// Posts a json summary of the update cycle
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub on: NotifyOn,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NtfyConfig {
+    #[serde(default = "ntfy_base_url")]
+    pub base_url: String,
+    pub topic: String,
+    /// An access token for protected topics
+    pub token: Option<String>,
+    /// From 1 (min) to 5 (max)
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub on: NotifyOn,
+}
+
+fn ntfy_base_url() -> String {
+    String::from("https://ntfy.sh")
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GotifyConfig {
+    pub base_url: String,
+    /// The token of the application that the messages are sent as
+    pub token: String,
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub on: NotifyOn,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the standard port of the tls mode
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub on: NotifyOn,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plaintext connection (port 587)
+    #[default]
+    StartTls,
+
+    /// Connect over tls from the start (port 465)
+    Tls,
+
+    /// Never encrypt, which is only suitable for a relay on the local network (port 25)
+    None,
+}
+
 #[derive(Deserialize, Clone, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct LogConfig {
@@ -105,18 +582,125 @@ pub enum DomainConfig {
     NoIp(NoIpConfig),
     Dynu(DynuConfig),
     Porkbun(PorkbunConfig),
+    Rfc2136(Rfc2136Config),
 }
 
+/// The type of each provider, as written in the config
+pub const PROVIDERS: [&str; 8] = [
+    "cloudflare",
+    "godaddy",
+    "namecheap",
+    "he",
+    "noip",
+    "dynu",
+    "porkbun",
+    "rfc2136",
+];
+
 impl DomainConfig {
     pub fn display_name(&self) -> String {
+        format!("{} ({})", self.zone(), self.provider())
+    }
+
+    pub fn provider(&self) -> &'static str {
+        match self {
+            DomainConfig::Cloudflare(_) => "cloudflare",
+            DomainConfig::GoDaddy(_) => "godaddy",
+            DomainConfig::Namecheap(_) => "namecheap",
+            DomainConfig::He(_) => "he",
+            DomainConfig::NoIp(_) => "noip",
+            DomainConfig::Dynu(_) => "dynu",
+            DomainConfig::Porkbun(_) => "porkbun",
+            DomainConfig::Rfc2136(_) => "rfc2136",
+        }
+    }
+
+    /// The domain (or zone, or hostname) that the records belong to
+    pub fn zone(&self) -> &str {
+        match self {
+            DomainConfig::Cloudflare(c) => c.zone_label(),
+            DomainConfig::GoDaddy(c) => &c.domain,
+            DomainConfig::Namecheap(c) => &c.domain,
+            DomainConfig::He(c) => &c.hostname,
+            DomainConfig::NoIp(c) => &c.hostname,
+            DomainConfig::Dynu(c) => &c.hostname,
+            DomainConfig::Porkbun(c) => &c.domain,
+            DomainConfig::Rfc2136(c) => &c.zone,
+        }
+    }
+
+    /// The hooks specific to this domain, as (on_update, on_failure)
+    pub fn hooks(&self) -> (Option<&str>, Option<&str>) {
+        let (on_update, on_failure) = match self {
+            DomainConfig::Cloudflare(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::GoDaddy(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::Namecheap(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::He(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::NoIp(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::Dynu(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::Porkbun(c) => (&c.on_update, &c.on_failure),
+            DomainConfig::Rfc2136(c) => (&c.on_update, &c.on_failure),
+        };
+        (on_update.as_deref(), on_failure.as_deref())
+    }
+
+    /// The records as written in the config
+    pub fn records(&self) -> Vec<String> {
         match self {
-            DomainConfig::Cloudflare(c) => format!("{} ({})", c.zone, "cloudflare"),
-            DomainConfig::GoDaddy(c) => format!("{} ({})", c.domain, "godaddy"),
-            DomainConfig::Namecheap(c) => format!("{} ({})", c.domain, "namecheap"),
-            DomainConfig::He(c) => format!("{} ({})", c.hostname, "he"),
-            DomainConfig::NoIp(c) => format!("{} ({})", c.hostname, "noip"),
-            DomainConfig::Dynu(c) => format!("{} ({})", c.hostname, "dynu"),
-            DomainConfig::Porkbun(c) => format!("{} ({})", c.domain, "porkbun"),
+            DomainConfig::Cloudflare(c) => c.records.clone(),
+            DomainConfig::GoDaddy(c) => c.records.clone(),
+            DomainConfig::Namecheap(c) => c.records.clone(),
+            DomainConfig::He(c) => c.records.clone(),
+            DomainConfig::NoIp(c) => vec![c.hostname.clone()],
+            DomainConfig::Dynu(c) => c.records.clone(),
+            DomainConfig::Porkbun(c) => c.records.clone(),
+            DomainConfig::Rfc2136(c) => c.records.clone(),
+        }
+    }
+
+    /// The interface identifiers of records whose AAAA address is built from the resolved
+    /// address's prefix, along with the length of that prefix
+    pub fn ipv6_suffixes(&self) -> Option<(&HashMap<String, Ipv6Addr>, u8)> {
+        match self {
+            DomainConfig::Cloudflare(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::GoDaddy(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::He(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::Dynu(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::Porkbun(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::Rfc2136(c) => Some((&c.suffixes, c.prefix_length)),
+            DomainConfig::Namecheap(_) | DomainConfig::NoIp(_) => None,
+        }
+    }
+
+    /// A copy of the domain that only updates the given records
+    pub fn with_records(&self, records: Vec<String>) -> DomainConfig {
+        let mut domain = self.clone();
+        match &mut domain {
+            DomainConfig::Cloudflare(c) => c.records = records,
+            DomainConfig::GoDaddy(c) => c.records = records,
+            DomainConfig::Namecheap(c) => c.records = records,
+            DomainConfig::He(c) => c.records = records,
+            DomainConfig::NoIp(_) => {}
+            DomainConfig::Dynu(c) => c.records = records,
+            DomainConfig::Porkbun(c) => c.records = records,
+            DomainConfig::Rfc2136(c) => c.records = records,
+        }
+        domain
+    }
+
+    /// Whether properties of the records beyond their address are kept in line. Those can drift
+    /// (or be changed in the config) without the address changing, so only the provider can tell
+    /// whether the records are current.
+    pub fn manages_record_settings(&self) -> bool {
+        match self {
+            DomainConfig::Cloudflare(c) => {
+                c.proxied.is_some()
+                    || c.ttl.is_some()
+                    || c.comment.is_some()
+                    || c.tags.is_some()
+                    || !c.overrides.is_empty()
+            }
+            _ => false,
         }
     }
 
@@ -124,11 +708,13 @@ impl DomainConfig {
         match self {
             DomainConfig::Cloudflare(c) => c.ip_types.clone(),
             DomainConfig::GoDaddy(c) => c.ip_types.clone(),
-            DomainConfig::Namecheap(_) => vec![IpType::V4],
+            // Namecheap's dynamic dns api can only update A records
+            DomainConfig::Namecheap(_) => ipv4_only(),
             DomainConfig::He(c) => c.ip_types.clone(),
             DomainConfig::NoIp(c) => c.ip_types.clone(),
             DomainConfig::Dynu(c) => c.ip_types.clone(),
             DomainConfig::Porkbun(c) => c.ip_types.clone(),
+            DomainConfig::Rfc2136(c) => c.ip_types.clone(),
         }
     }
 }
@@ -142,7 +728,7 @@ pub enum IpType {
 }
 
 impl IpType {
-    pub fn record_type(&self) -> &str {
+    pub fn record_type(&self) -> &'static str {
         match self {
             IpType::V4 => "A",
             IpType::V6 => "AAAA",
@@ -163,16 +749,73 @@ fn ipv4_only() -> Vec<IpType> {
     vec![IpType::V4]
 }
 
+fn default_prefix_length() -> u8 {
+    64
+}
+
 #[derive(Deserialize, Clone, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct CloudflareConfig {
+    #[serde(default = "cloudflare_base_url")]
+    pub base_url: String,
     pub email: Option<String>,
     pub key: Option<String>,
     pub token: Option<String>,
-    pub zone: String,
+    pub zone: Option<String>,
+    pub zone_id: Option<String>,
     pub records: Vec<String>,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+    #[serde(default)]
+    pub create_missing: bool,
+    #[serde(default = "cloudflare_create_ttl")]
+    pub create_ttl: u32,
+    pub proxied: Option<bool>,
+    pub ttl: Option<u32>,
+    pub comment: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub overrides: HashMap<String, CloudflareRecordSettings>,
+}
+
+/// The properties dness keeps in line on a cloudflare record, beyond its address. Unset properties
+/// are left as they are.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CloudflareRecordSettings {
+    pub proxied: Option<bool>,
+    pub ttl: Option<u32>,
+    pub comment: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl CloudflareConfig {
+    /// The settings of a record, where those in the record's override take precedence over the
+    /// ones set for the whole domain
+    pub fn record_settings(&self, record: &str) -> CloudflareRecordSettings {
+        let overrides = self.overrides.get(record).cloned().unwrap_or_default();
+        CloudflareRecordSettings {
+            proxied: overrides.proxied.or(self.proxied),
+            ttl: overrides.ttl.or(self.ttl),
+            comment: overrides.comment.or_else(|| self.comment.clone()),
+            tags: overrides.tags.or_else(|| self.tags.clone()),
+        }
+    }
+
+    /// How the zone is referred to in logs and the state file: its name, otherwise its id. When
+    /// neither is configured, the zones are discovered from the records.
+    pub fn zone_label(&self) -> &str {
+        self.zone
+            .as_deref()
+            .or(self.zone_id.as_deref())
+            .unwrap_or("all zones")
+    }
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -186,6 +829,16 @@ pub struct GoDaddyConfig {
     pub records: Vec<String>,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+    #[serde(default)]
+    pub create_missing: bool,
+    #[serde(default = "godaddy_create_ttl")]
+    pub create_ttl: u32,
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -196,6 +849,8 @@ pub struct NamecheapConfig {
     pub domain: String,
     pub ddns_password: String,
     pub records: Vec<String>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -208,6 +863,12 @@ pub struct HeConfig {
     pub records: Vec<String>,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -220,6 +881,8 @@ pub struct NoIpConfig {
     pub hostname: String,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -233,6 +896,12 @@ pub struct DynuConfig {
     pub records: Vec<String>,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
 }
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -246,6 +915,74 @@ pub struct PorkbunConfig {
     pub records: Vec<String>,
     #[serde(default = "ipv4_only")]
     pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+    #[serde(default)]
+    pub create_missing: bool,
+    #[serde(default = "porkbun_create_ttl")]
+    pub create_ttl: u32,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rfc2136Config {
+    /// The primary name server of the zone, as a host or address with an optional port
+    pub server: String,
+    pub zone: String,
+    pub records: Vec<String>,
+    pub key_name: String,
+    /// The base64 encoded TSIG key, as found in the `secret` of a bind key file
+    pub key_secret: String,
+    #[serde(default)]
+    pub key_algorithm: TsigAlgorithm,
+    #[serde(default = "rfc2136_ttl")]
+    pub ttl: u32,
+    #[serde(default = "ipv4_only")]
+    pub ip_types: Vec<IpType>,
+    pub on_update: Option<String>,
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub suffixes: HashMap<String, Ipv6Addr>,
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TsigAlgorithm {
+    #[default]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+#[cfg(test)]
+impl GoDaddyConfig {
+    /// A dual stack domain of example.com with the given records
+    pub fn for_test(records: &[&str]) -> Self {
+        GoDaddyConfig {
+            base_url: String::from("http://localhost"),
+            domain: String::from("example.com"),
+            key: String::from("key-1"),
+            secret: String::from("secret-1"),
+            records: records.iter().map(|x| String::from(*x)).collect(),
+            ip_types: vec![IpType::V4, IpType::V6],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
+        }
+    }
+}
+
+fn cloudflare_base_url() -> String {
+    String::from("https://api.cloudflare.com/client/v4")
 }
 
 fn godaddy_base_url() -> String {
@@ -272,6 +1009,24 @@ fn porkbun_base_url() -> String {
     String::from("https://api.porkbun.com/api/json/v3")
 }
 
+/// A ttl of 1 is cloudflare's "automatic"
+fn cloudflare_create_ttl() -> u32 {
+    1
+}
+
+/// GoDaddy and Porkbun both reject a ttl lower than 600 seconds
+fn godaddy_create_ttl() -> u32 {
+    600
+}
+
+fn porkbun_create_ttl() -> u32 {
+    600
+}
+
+fn rfc2136_ttl() -> u32 {
+    300
+}
+
 pub fn parse_config<P: AsRef<Path>>(path: P) -> Result<DnsConfig, ConfigError> {
     let mut f = File::open(path).map_err(|e| ConfigError {
         kind: ConfigErrorKind::FileNotFound(e),
@@ -314,7 +1069,20 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("opendns"),
+                ip_resolver: vec![String::from("opendns")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Info,
                 },
@@ -337,17 +1105,43 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("opendns"),
+                ip_resolver: vec![String::from("opendns")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Info,
                 },
                 domains: vec![DomainConfig::Cloudflare(CloudflareConfig {
+                    base_url: String::from("https://api.cloudflare.com/client/v4"),
                     email: None,
                     key: None,
                     token: Some(String::from("dec0de")),
-                    zone: String::from("example.com"),
+                    zone: Some(String::from("example.com")),
+                    zone_id: None,
                     records: vec![String::from("n.example.com")],
                     ip_types: vec![IpType::V4],
+                    on_update: None,
+                    on_failure: None,
+                    suffixes: HashMap::new(),
+                    prefix_length: 64,
+                    create_missing: false,
+                    create_ttl: 1,
+                    proxied: None,
+                    ttl: None,
+                    comment: None,
+                    tags: None,
+                    overrides: HashMap::new(),
                 })]
             }
         );
@@ -360,17 +1154,43 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("opendns"),
+                ip_resolver: vec![String::from("opendns")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Info,
                 },
                 domains: vec![DomainConfig::Cloudflare(CloudflareConfig {
+                    base_url: String::from("https://api.cloudflare.com/client/v4"),
                     email: None,
                     key: None,
                     token: Some(String::from("dec0de")),
-                    zone: String::from("example.com"),
+                    zone: Some(String::from("example.com")),
+                    zone_id: None,
                     records: vec![String::from("n.example.com")],
                     ip_types: vec![IpType::V6],
+                    on_update: None,
+                    on_failure: None,
+                    suffixes: HashMap::new(),
+                    prefix_length: 64,
+                    create_missing: false,
+                    create_ttl: 1,
+                    proxied: None,
+                    ttl: None,
+                    comment: None,
+                    tags: None,
+                    overrides: HashMap::new(),
                 })]
             }
         );
@@ -383,22 +1203,322 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("opendns"),
+                ip_resolver: vec![String::from("opendns")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Info,
                 },
                 domains: vec![DomainConfig::Cloudflare(CloudflareConfig {
+                    base_url: String::from("https://api.cloudflare.com/client/v4"),
                     email: None,
                     key: None,
                     token: Some(String::from("dec0de")),
-                    zone: String::from("example.com"),
+                    zone: Some(String::from("example.com")),
+                    zone_id: None,
                     records: vec![String::from("n.example.com")],
                     ip_types: vec![IpType::V4, IpType::V6],
+                    on_update: None,
+                    on_failure: None,
+                    suffixes: HashMap::new(),
+                    prefix_length: 64,
+                    create_missing: false,
+                    create_ttl: 1,
+                    proxied: None,
+                    ttl: None,
+                    comment: None,
+                    tags: None,
+                    overrides: HashMap::new(),
                 })]
             }
         )
     }
 
+    #[test]
+    fn deserialize_config_daemon() {
+        let toml_str = &include_str!("../assets/daemon-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.interval, 60);
+        assert_eq!(
+            config.state_file,
+            Some(PathBuf::from("/var/lib/dness/state.json"))
+        );
+        assert_eq!(config.domains.len(), 1);
+    }
+
+    #[test]
+    fn deserialize_config_cloudflare_zones() {
+        let toml_str = &include_str!("../assets/cloudflare-zones-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        let names = config
+            .domains
+            .iter()
+            .map(|x| x.display_name())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            vec![
+                String::from("aaaabbbb (cloudflare)"),
+                String::from("all zones (cloudflare)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_config_cloudflare_settings() {
+        let toml_str = &include_str!("../assets/cloudflare-settings-config.toml");
+        let config: DomainConfig = toml::from_str(toml_str).unwrap();
+        let config = match config {
+            DomainConfig::Cloudflare(c) => c,
+            _ => panic!("expected a cloudflare config"),
+        };
+
+        assert_eq!(
+            config.record_settings("n.example.com"),
+            CloudflareRecordSettings {
+                proxied: Some(false),
+                ttl: Some(300),
+                comment: Some(String::from("managed by dness")),
+                tags: Some(vec![String::from("dness")]),
+            }
+        );
+        assert_eq!(
+            config.record_settings("n2.example.com"),
+            CloudflareRecordSettings {
+                proxied: Some(true),
+                ttl: Some(300),
+                comment: Some(String::from("managed by dness")),
+                tags: Some(vec![]),
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_interface() {
+        let toml_str = &include_str!("../assets/interface-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.ip_resolver, vec![String::from("interface")]);
+        assert_eq!(
+            config.resolvers.interface,
+            Some(InterfaceResolverConfig {
+                name: String::from("eth0"),
+                prefer: Some(Ipv6Preference::StablePrivacy),
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_config_http_resolver() {
+        let toml_str = &include_str!("../assets/http-resolver-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("User-Agent"), String::from("dness"));
+        assert_eq!(
+            config.resolvers.http,
+            Some(HttpResolverConfig {
+                v4_url: Some(String::from("https://1.1.1.1/cdn-cgi/trace")),
+                v6_url: Some(String::from("https://[2606:4700:4700::1111]/cdn-cgi/trace")),
+                headers,
+                regex: Some(String::from("(?m)^ip=(.+)$")),
+                json_pointer: None,
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_config_multiple_resolvers() {
+        let toml_str = &include_str!("../assets/multi-resolver-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.ip_resolver,
+            vec![
+                String::from("opendns"),
+                String::from("stun"),
+                String::from("http")
+            ]
+        );
+        assert_eq!(config.resolvers.strategy, ResolverStrategy::Quorum);
+        assert_eq!(config.resolvers.quorum, Some(2));
+        assert_eq!(config.resolvers.transport, DnsTransport::Tls);
+        assert_eq!(
+            config.resolvers.stun,
+            Some(StunResolverConfig {
+                servers: vec![
+                    String::from("stun.example.com:3478"),
+                    String::from("[2001:db8::3]:3478")
+                ]
+            })
+        );
+        assert_eq!(
+            config.resolvers.natpmp,
+            Some(NatPmpResolverConfig {
+                gateway: Some(String::from("192.168.1.1"))
+            })
+        );
+        assert_eq!(config.resolvers.upnp, None);
+
+        let config: DnsConfig = toml::from_str("").unwrap();
+        assert_eq!(config.resolvers.strategy, ResolverStrategy::FirstSuccess);
+    }
+
+    #[test]
+    fn deserialize_config_ipv6_suffixes() {
+        let toml_str = &include_str!("../assets/ipv6-suffixes-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        let (suffixes, prefix_length) = config.domains[0].ipv6_suffixes().unwrap();
+        assert_eq!(prefix_length, 56);
+        assert_eq!(suffixes.len(), 2);
+        assert_eq!(
+            suffixes.get("www.example.com"),
+            Some(&Ipv6Addr::new(0, 0, 0, 1, 0, 0, 0, 0x80))
+        );
+    }
+
+    #[test]
+    fn deserialize_config_hooks() {
+        let toml_str = &include_str!("../assets/hooks-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.on_update.as_deref(),
+            Some("logger -t dness \"$DNESS_RECORD is now $DNESS_NEW_IP\"")
+        );
+        assert_eq!(
+            config.on_failure.as_deref(),
+            Some("notify-send dness \"$DNESS_ERROR\"")
+        );
+        assert_eq!(config.hook_timeout, 10);
+        assert_eq!(config.domains[0].hooks(), (None, None));
+        assert_eq!(
+            config.domains[1].hooks(),
+            (Some("/usr/local/bin/reload-firewall"), None)
+        );
+    }
+
+    #[test]
+    fn deserialize_config_notify() {
+        let toml_str = &include_str!("../assets/notify-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("Authorization"), String::from("Bearer abc123"));
+        assert_eq!(
+            config.notify,
+            vec![
+                NotifyConfig::Webhook(WebhookConfig {
+                    url: String::from("https://example.com/dness"),
+                    headers,
+                    on: NotifyOn::Both,
+                }),
+                NotifyConfig::Ntfy(NtfyConfig {
+                    base_url: String::from("https://ntfy.sh"),
+                    topic: String::from("dness-home"),
+                    token: None,
+                    priority: Some(4),
+                    on: NotifyOn::Change,
+                }),
+                NotifyConfig::Gotify(GotifyConfig {
+                    base_url: String::from("https://gotify.example.com"),
+                    token: String::from("AbC123"),
+                    priority: None,
+                    on: NotifyOn::Failure,
+                }),
+                NotifyConfig::Smtp(SmtpConfig {
+                    host: String::from("smtp.example.com"),
+                    port: None,
+                    tls: SmtpTls::StartTls,
+                    username: Some(String::from("dness@example.com")),
+                    password: Some(String::from("super_secret_password")),
+                    from: String::from("dness <dness@example.com>"),
+                    to: vec![String::from("admin@example.com")],
+                    on: NotifyOn::Both,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_config_metrics() {
+        let toml_str = &include_str!("../assets/metrics-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.metrics,
+            MetricsConfig {
+                listen: Some(String::from("127.0.0.1:9184")),
+                textfile: Some(PathBuf::from(
+                    "/var/lib/node_exporter/textfile_collector/dness.prom"
+                )),
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_lookup() {
+        let toml_str = &include_str!("../assets/lookup-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.lookup,
+            LookupConfig {
+                transport: DnsTransport::Https,
+                authoritative: true,
+                verify: true,
+                verify_timeout: 120,
+                verify_interval: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_http() {
+        let toml_str = &include_str!("../assets/http-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.http,
+            HttpConfig {
+                connect_timeout: 5,
+                timeout: 20,
+                proxy: Some(String::from("socks5h://127.0.0.1:1080")),
+                local_address: Some("203.0.113.5".parse().unwrap()),
+                interface: Some(String::from("wan0")),
+                user_agent: String::from("dness-gateway/1.0 admin@example.com"),
+                ca_certs: vec![PathBuf::from("/etc/dness/proxy-ca.pem")],
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_retry() {
+        let toml_str = &include_str!("../assets/retry-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.retry,
+            RetryConfig {
+                attempts: 5,
+                base_delay: 0.5,
+                max_delay: 60.0,
+                statuses: vec![429, 502, 503],
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_concurrency() {
+        let toml_str = &include_str!("../assets/concurrency-config.toml");
+        let config: DnsConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.max_concurrency, 8);
+        assert_eq!(config.provider_concurrency.get("godaddy"), Some(&1));
+        assert_eq!(config.provider_concurrency.get("namecheap"), Some(&2));
+    }
+
     #[test]
     fn deserialize_config_godaddy() {
         let toml_str = &include_str!("../assets/godaddy-config.toml");
@@ -411,7 +1531,13 @@ mod tests {
                 key: String::from("abc123"),
                 secret: String::from("ef"),
                 records: vec![String::from("@")],
-                ip_types: vec![IpType::V4]
+                ip_types: vec![IpType::V4],
+                on_update: None,
+                on_failure: None,
+                suffixes: HashMap::new(),
+                prefix_length: 64,
+                create_missing: false,
+                create_ttl: 600,
             })
         );
     }
@@ -426,9 +1552,15 @@ mod tests {
                 base_url: String::from("https://dynamicdns.park-your-domain.com"),
                 domain: String::from("test-dness-1.xyz"),
                 ddns_password: String::from("super_secret_password"),
-                records: vec![String::from("@"), String::from("*"), String::from("sub")]
+                records: vec![String::from("@"), String::from("*"), String::from("sub")],
+                on_update: None,
+                on_failure: None,
             })
         );
+
+        // Namecheap only updates A records, so there are no ip types to choose from
+        let with_ipv6 = format!("{}ip_types = [\"4\", \"6\"]\n", toml_str);
+        assert!(toml::from_str::<DomainConfig>(&with_ipv6).is_err());
     }
 
     #[test]
@@ -442,7 +1574,11 @@ mod tests {
                 hostname: String::from("test-dness-1.xyz"),
                 password: String::from("super_secret_password"),
                 records: vec![String::from("@"), String::from("sub")],
-                ip_types: vec![IpType::V4]
+                ip_types: vec![IpType::V4],
+                on_update: None,
+                on_failure: None,
+                suffixes: HashMap::new(),
+                prefix_length: 64
             })
         );
     }
@@ -454,29 +1590,68 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("opendns"),
+                ip_resolver: vec![String::from("opendns")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Debug,
                 },
                 domains: vec![
                     DomainConfig::Cloudflare(CloudflareConfig {
+                        base_url: String::from("https://api.cloudflare.com/client/v4"),
                         email: None,
                         key: None,
                         token: Some(String::from("dec0de")),
-                        zone: String::from("example.com"),
+                        zone: Some(String::from("example.com")),
+                        zone_id: None,
                         records: vec![String::from("n.example.com")],
                         ip_types: vec![IpType::V4],
+                        on_update: None,
+                        on_failure: None,
+                        suffixes: HashMap::new(),
+                        prefix_length: 64,
+                        create_missing: false,
+                        create_ttl: 1,
+                        proxied: None,
+                        ttl: None,
+                        comment: None,
+                        tags: None,
+                        overrides: HashMap::new(),
                     }),
                     DomainConfig::Cloudflare(CloudflareConfig {
+                        base_url: String::from("https://api.cloudflare.com/client/v4"),
                         email: Some(String::from("admin@example.com")),
                         key: Some(String::from("deadbeef")),
                         token: None,
-                        zone: String::from("example2.com"),
+                        zone: Some(String::from("example2.com")),
+                        zone_id: None,
                         records: vec![
                             String::from("n.example2.com"),
                             String::from("n2.example2.com")
                         ],
                         ip_types: vec![IpType::V4],
+                        on_update: None,
+                        on_failure: None,
+                        suffixes: HashMap::new(),
+                        prefix_length: 64,
+                        create_missing: false,
+                        create_ttl: 1,
+                        proxied: None,
+                        ttl: None,
+                        comment: None,
+                        tags: None,
+                        overrides: HashMap::new(),
                     })
                 ]
             }
@@ -497,7 +1672,20 @@ mod tests {
         assert_eq!(
             config,
             DnsConfig {
-                ip_resolver: String::from("ipify"),
+                ip_resolver: vec![String::from("ipify")],
+                interval: 300,
+                max_concurrency: 4,
+                provider_concurrency: BTreeMap::new(),
+                state_file: None,
+                on_update: None,
+                on_failure: None,
+                hook_timeout: 60,
+                resolvers: ResolversConfig::default(),
+                notify: Vec::new(),
+                metrics: MetricsConfig::default(),
+                lookup: LookupConfig::default(),
+                http: HttpConfig::default(),
+                retry: RetryConfig::default(),
                 log: LogConfig {
                     level: LevelFilter::Info,
                 },
@@ -518,6 +1706,8 @@ mod tests {
                 hostname: String::from("dnesstest.hopto.org"),
                 password: String::from("super_secret_password"),
                 ip_types: vec![IpType::V4],
+                on_update: None,
+                on_failure: None,
             })
         );
     }
@@ -534,7 +1724,34 @@ mod tests {
                 username: String::from("MyUserName"),
                 password: String::from("IpUpdatePassword"),
                 records: vec![String::from("@"), String::from("sub")],
-                ip_types: vec![IpType::V4]
+                ip_types: vec![IpType::V4],
+                on_update: None,
+                on_failure: None,
+                suffixes: HashMap::new(),
+                prefix_length: 64
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_config_rfc2136() {
+        let toml_str = &include_str!("../assets/rfc2136-config.toml");
+        let config: DomainConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config,
+            DomainConfig::Rfc2136(Rfc2136Config {
+                server: String::from("ns1.example.com:5353"),
+                zone: String::from("example.com"),
+                records: vec![String::from("@"), String::from("home")],
+                key_name: String::from("dness-key"),
+                key_secret: String::from("c2VjcmV0LWtleS1mb3ItZG5lc3M="),
+                key_algorithm: TsigAlgorithm::HmacSha512,
+                ttl: 60,
+                ip_types: vec![IpType::V4, IpType::V6],
+                on_update: None,
+                on_failure: None,
+                suffixes: HashMap::new(),
+                prefix_length: 64
             })
         );
     }
@@ -1,9 +1,168 @@
+use crate::authoritative::NameServers;
+use crate::config::{IpType, LookupConfig, RetryConfig};
+use crate::dns::DnsResolver;
 use log::warn;
+use serde::Serialize;
 use std::collections::HashSet;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops::{Add, AddAssign};
+use std::sync::Mutex;
 
-#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+/// Clients shared by every provider. They are created once at startup so that http connections
+/// and dns resolvers are reused across domains and, when running as a daemon, across update cycles.
+#[derive(Clone, Debug, Copy)]
+pub struct Context<'a> {
+    pub http: &'a reqwest::Client,
+    pub resolver: &'a DnsResolver,
+
+    /// How failed requests to providers are retried
+    pub retry: &'a RetryConfig,
+
+    /// Where the current address of records is looked up, for the providers that compare over dns
+    pub lookup: &'a LookupConfig,
+
+    /// The authoritative name servers asked so far, for lookups that skip the public resolver
+    pub name_servers: &'a NameServers,
+
+    /// Compare records as usual but only print the changes instead of sending them
+    pub dry_run: bool,
+
+    /// Where providers note each record they change, for the hooks
+    pub changes: &'a ChangeLog,
+}
+
+#[cfg(test)]
+impl<'a> Context<'a> {
+    /// A context with the default settings that isn't a dry run. Its changes go to a log shared by
+    /// every test and never read, so tests that check the changes bring their own.
+    pub fn for_test(http: &'a reqwest::Client, resolver: &'a DnsResolver) -> Self {
+        use std::sync::OnceLock;
+        static RETRY: OnceLock<RetryConfig> = OnceLock::new();
+        static LOOKUP: OnceLock<LookupConfig> = OnceLock::new();
+        static NAME_SERVERS: OnceLock<NameServers> = OnceLock::new();
+        static CHANGES: OnceLock<ChangeLog> = OnceLock::new();
+        Context {
+            http,
+            resolver,
+            retry: RETRY.get_or_init(RetryConfig::default),
+            lookup: LOOKUP.get_or_init(LookupConfig::default),
+            name_servers: NAME_SERVERS.get_or_init(NameServers::default),
+            dry_run: false,
+            changes: CHANGES.get_or_init(ChangeLog::default),
+        }
+    }
+}
+
+/// A record that was pointed at a new address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordChange {
+    pub record: String,
+    pub zone: String,
+
+    /// The previous content of the record, which is empty when the record was created
+    pub old: String,
+    pub new: IpAddr,
+}
+
+/// What became of a configured record during an update
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStatus {
+    Updated,
+    Current,
+    Missing,
+
+    /// The address was current but the record's other settings had to be corrected
+    Corrected,
+}
+
+/// The changes (made or, on a dry run, planned) and the records left as they were
+#[derive(Debug, Default)]
+pub struct ChangeLog {
+    changes: Mutex<Vec<RecordChange>>,
+    unchanged: Mutex<Vec<(String, RecordStatus)>>,
+
+    /// Planned changes are only collected instead of also printed, for the json report
+    quiet: bool,
+}
+
+impl ChangeLog {
+    pub fn quiet() -> Self {
+        ChangeLog {
+            quiet: true,
+            ..ChangeLog::default()
+        }
+    }
+
+    pub fn push(&self, record: &str, zone: &str, old: &str, new: IpAddr) {
+        let change = RecordChange {
+            record: String::from(record),
+            zone: String::from(zone),
+            old: String::from(old),
+            new,
+        };
+        self.changes.lock().unwrap().push(change);
+    }
+
+    /// Notes a change that would have been made on a dry run, where an empty `old` is a record that
+    /// would be created
+    pub fn plan(&self, record: &str, zone: &str, old: &str, new: IpAddr) {
+        self.plan_settings(record, zone, old, new, &[]);
+    }
+
+    /// Like `plan`, but also lists the record settings that would be corrected
+    pub fn plan_settings(
+        &self,
+        record: &str,
+        zone: &str,
+        old: &str,
+        new: IpAddr,
+        settings: &[&str],
+    ) {
+        if !self.quiet {
+            let shown = if old.is_empty() { "(missing)" } else { old };
+            print_plan_settings(record, shown, new, settings);
+        }
+        self.push(record, zone, old, new);
+    }
+
+    /// Notes a record whose address was current but whose settings were corrected
+    pub fn corrected(&self, record: &str) {
+        let status = (String::from(record), RecordStatus::Corrected);
+        self.unchanged.lock().unwrap().push(status);
+    }
+
+    /// Like `corrected`, but for a dry run, where the correction is printed instead
+    pub fn plan_corrected(&self, record: &str, addr: IpAddr, settings: &[&str]) {
+        if !self.quiet {
+            print_plan_settings(record, &addr.to_string(), addr, settings);
+        }
+        self.corrected(record);
+    }
+
+    pub fn current(&self, record: &str) {
+        let status = (String::from(record), RecordStatus::Current);
+        self.unchanged.lock().unwrap().push(status);
+    }
+
+    pub fn missing(&self, record: &str) {
+        let status = (String::from(record), RecordStatus::Missing);
+        self.unchanged.lock().unwrap().push(status);
+    }
+
+    /// Removes and returns the changes noted so far
+    pub fn take(&self) -> Vec<RecordChange> {
+        std::mem::take(&mut *self.changes.lock().unwrap())
+    }
+
+    /// Removes and returns the records noted as current, corrected, or missing so far
+    pub fn take_unchanged(&self) -> Vec<(String, RecordStatus)> {
+        std::mem::take(&mut *self.unchanged.lock().unwrap())
+    }
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Serialize)]
 pub struct Updates {
     pub updated: i32,
     pub current: i32,
@@ -38,16 +197,24 @@ impl fmt::Display for Updates {
     }
 }
 
+/// The expected records that are absent from the actual records, sorted for stable output
+pub fn missing_records(expected: &HashSet<String>, actual: &HashSet<String>) -> Vec<String> {
+    let mut missing = expected
+        .difference(actual)
+        .cloned()
+        .collect::<Vec<String>>();
+    missing.sort();
+    missing
+}
+
+/// Logs the expected records that are absent from the actual records and returns them
 pub fn log_missing_domains(
     expected: &HashSet<String>,
     actual: &HashSet<String>,
     provider: &str,
     domain: &str,
-) -> usize {
-    let missing_domains = expected
-        .difference(actual)
-        .cloned()
-        .collect::<Vec<String>>();
+) -> Vec<String> {
+    let missing_domains = missing_records(expected, actual);
 
     if !missing_domains.is_empty() {
         warn!(
@@ -58,5 +225,33 @@ pub fn log_missing_domains(
         );
     }
 
-    missing_domains.len()
+    missing_domains
+}
+
+/// Prints a change that would have been made on a dry run, eg: "n.example.com A 1.1.1.1 -> 2.2.2.2",
+/// along with the record settings that would be corrected, eg:
+/// "n.example.com A 2.2.2.2 -> 2.2.2.2 (proxied, ttl)"
+fn print_plan_settings(record: &str, old: &str, new: IpAddr, settings: &[&str]) {
+    let mut line = format!(
+        "{} {} {} -> {}",
+        record,
+        IpType::from(new).record_type(),
+        old,
+        new
+    );
+
+    if !settings.is_empty() {
+        line.push_str(&format!(" ({})", settings.join(", ")));
+    }
+
+    println!("{}", line);
+}
+
+/// Expands a record relative to its domain into a fully qualified name, where "@" is the domain
+pub fn fqdn(record: &str, domain: &str) -> String {
+    if record == "@" || record.is_empty() {
+        String::from(domain)
+    } else {
+        format!("{}.{}", record, domain)
+    }
 }
@@ -1,60 +1,140 @@
-use crate::config::IpType;
+use crate::config::{DnsTransport, IpType, RetryConfig};
 use crate::errors::{DnsError, DnsErrorKind};
-use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig};
-use hickory_resolver::name_server::TokioConnectionProvider;
-use hickory_resolver::TokioResolver;
+use crate::retry::retry;
+use futures::StreamExt;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::name_server::{NameServerPool, TokioConnectionProvider};
+use hickory_resolver::proto::op::Query;
+use hickory_resolver::proto::rr::{DNSClass, RData, RecordType};
+use hickory_resolver::proto::xfer::{DnsHandle, DnsRequestOptions, DnsResponse};
+use hickory_resolver::{IntoName, ResolveError, TokioResolver};
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-#[derive(Debug)]
+/// The name servers at the addresses, reached over the transport. Encrypted transports check that
+/// the servers' certificates are valid for the tls name.
+fn name_servers(ips: &[IpAddr], transport: DnsTransport, tls_name: &str) -> NameServerConfigGroup {
+    let tls_name = String::from(tls_name);
+    match transport {
+        DnsTransport::Udp => NameServerConfigGroup::from_ips_clear(ips, 53, false),
+        DnsTransport::Tls => NameServerConfigGroup::from_ips_tls(ips, 853, tls_name, false),
+        DnsTransport::Https => NameServerConfigGroup::from_ips_https(ips, 443, tls_name, false),
+    }
+}
+
+/// Whether the lookup failed for a reason other than the name not having any records
+fn transient(err: &ResolveError) -> bool {
+    !err.is_no_records_found()
+}
+
 pub struct DnsResolver {
     resolver: TokioResolver,
+
+    // The resolver only asks questions of the internet (IN) class, so queries in other classes
+    // are sent to the name servers directly
+    pool: NameServerPool<TokioConnectionProvider>,
+
+    /// Lookups that time out or fail to connect are retried, while those that find no records
+    /// are not
+    retry: RetryConfig,
+}
+
+impl fmt::Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsResolver")
+            .field("resolver", &self.resolver)
+            .finish_non_exhaustive()
+    }
 }
 
 impl DnsResolver {
-    pub async fn create_opendns(ip_type: IpType) -> Result<Self, DnsError> {
-        let ips = // OpenDNS nameservers:
-                // https://en.wikipedia.org/wiki/OpenDNS#Name_server_IP_addresses
-                match ip_type {
-                    IpType::V4 => [
-                        IpAddr::V4(Ipv4Addr::new(208, 67, 222, 222)),
-                        IpAddr::V4(Ipv4Addr::new(208, 67, 220, 220)),
-                    ],
-                    IpType::V6 => [
-                        IpAddr::V6(Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35)),
-                        IpAddr::V6(Ipv6Addr::new(0x2620, 0x119, 0x53, 0, 0, 0, 0, 0x53)),
-                    ],
-                };
-
-        let config = ResolverConfig::from_parts(
-            None,
-            vec![],
-            NameServerConfigGroup::from_ips_clear(&ips, 53, false),
-        );
+    pub async fn create_opendns(
+        ip_type: IpType,
+        transport: DnsTransport,
+    ) -> Result<Self, DnsError> {
+        let ips = match (transport, ip_type) {
+            // OpenDNS nameservers:
+            // https://en.wikipedia.org/wiki/OpenDNS#Name_server_IP_addresses
+            (DnsTransport::Udp | DnsTransport::Tls, IpType::V4) => vec![
+                IpAddr::V4(Ipv4Addr::new(208, 67, 222, 222)),
+                IpAddr::V4(Ipv4Addr::new(208, 67, 220, 220)),
+            ],
+            (DnsTransport::Udp | DnsTransport::Tls, IpType::V6) => vec![
+                IpAddr::V6(Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35)),
+                IpAddr::V6(Ipv6Addr::new(0x2620, 0x119, 0x53, 0, 0, 0, 0, 0x53)),
+            ],
+            // DNS over HTTPS is served from doh.opendns.com instead
+            (DnsTransport::Https, IpType::V4) => vec![IpAddr::V4(Ipv4Addr::new(146, 112, 41, 2))],
+            (DnsTransport::Https, IpType::V6) => vec![IpAddr::V6(Ipv6Addr::new(
+                0x2620, 0x119, 0xfc, 0, 0, 0, 0, 0x2,
+            ))],
+        };
+
+        let tls_name = match transport {
+            DnsTransport::Https => "doh.opendns.com",
+            _ => "dns.opendns.com",
+        };
+
+        Self::from_servers(&ips, transport, tls_name).await
+    }
 
+    pub async fn from_ips(ips: &[IpAddr]) -> Result<Self, DnsError> {
+        Self::from_servers(ips, DnsTransport::Udp, "").await
+    }
+
+    pub async fn from_servers(
+        ips: &[IpAddr],
+        transport: DnsTransport,
+        tls_name: &str,
+    ) -> Result<Self, DnsError> {
+        let config =
+            ResolverConfig::from_parts(None, vec![], name_servers(ips, transport, tls_name));
         Self::from_config(config).await
     }
 
-    pub async fn create_cloudflare() -> Result<Self, DnsError> {
-        Self::from_config(ResolverConfig::cloudflare()).await
+    pub async fn create_cloudflare(transport: DnsTransport) -> Result<Self, DnsError> {
+        let config = match transport {
+            DnsTransport::Udp => ResolverConfig::cloudflare(),
+            DnsTransport::Tls => ResolverConfig::cloudflare_tls(),
+            DnsTransport::Https => ResolverConfig::cloudflare_https(),
+        };
+        Self::from_config(config).await
     }
 
     pub async fn from_config(config: ResolverConfig) -> Result<Self, DnsError> {
-        let resolver = TokioResolver::builder_with_config(config, TokioConnectionProvider::default())
-            .build();
+        let pool = NameServerPool::from_config(
+            NameServerConfigGroup::from(config.name_servers().to_vec()),
+            ResolverOpts::default(),
+            TokioConnectionProvider::default(),
+        );
 
-        Ok(DnsResolver { resolver })
+        let resolver =
+            TokioResolver::builder_with_config(config, TokioConnectionProvider::default()).build();
+
+        Ok(DnsResolver {
+            resolver,
+            pool,
+            retry: RetryConfig::default(),
+        })
+    }
+
+    pub fn with_retry(self, retry: &RetryConfig) -> Self {
+        DnsResolver {
+            retry: retry.clone(),
+            ..self
+        }
     }
 
     pub async fn ipv4_lookup(&self, host: &str) -> Result<Ipv4Addr, DnsError> {
         // When we query opendns for the special domain of "myip.opendns.com" it will return to us
         // our IP
-        let response = self
-            .resolver
-            .ipv4_lookup(host)
-            .await
-            .map_err(|e| DnsError {
-                kind: Box::new(DnsErrorKind::DnsResolve(e)),
-            })?;
+        let response = retry(&self.retry, host, transient, || {
+            self.resolver.ipv4_lookup(host)
+        })
+        .await
+        .map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        })?;
 
         response
             .iter()
@@ -68,13 +148,13 @@ impl DnsResolver {
     pub async fn ipv6_lookup(&self, host: &str) -> Result<Ipv6Addr, DnsError> {
         // When we query opendns for the special domain of "myip.opendns.com" it will return to us
         // our IP
-        let response = self
-            .resolver
-            .ipv6_lookup(host)
-            .await
-            .map_err(|e| DnsError {
-                kind: Box::new(DnsErrorKind::DnsResolve(e)),
-            })?;
+        let response = retry(&self.retry, host, transient, || {
+            self.resolver.ipv6_lookup(host)
+        })
+        .await
+        .map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        })?;
 
         response
             .iter()
@@ -91,21 +171,163 @@ impl DnsResolver {
             IpType::V6 => self.ipv6_lookup(host).await?.into(),
         })
     }
+
+    pub async fn txt_lookup(&self, host: &str) -> Result<Vec<String>, DnsError> {
+        let response = retry(&self.retry, host, transient, || {
+            self.resolver.txt_lookup(host)
+        })
+        .await
+        .map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        })?;
+
+        Ok(response.iter().map(|txt| txt.to_string()).collect())
+    }
+
+    /// The names of the name servers that are authoritative for the zone
+    pub async fn ns_lookup(&self, zone: &str) -> Result<Vec<String>, DnsError> {
+        let response = retry(&self.retry, zone, transient, || {
+            self.resolver.ns_lookup(zone)
+        })
+        .await
+        .map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        })?;
+
+        Ok(response.iter().map(|ns| ns.0.to_string()).collect())
+    }
+
+    /// Sends the query straight to the name servers, bypassing the resolver's cache
+    async fn direct_lookup(&self, host: &str, query: Query) -> Result<DnsResponse, DnsError> {
+        retry(&self.retry, host, transient, || async {
+            self.pool
+                .lookup(query.clone(), DnsRequestOptions::default())
+                .next()
+                .await
+                .ok_or_else(|| ResolveError::from("no response from name server"))?
+                .map_err(ResolveError::from)
+        })
+        .await
+        .map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        })
+    }
+
+    /// Looks up the address of the host by asking the name servers directly, so that the answer is
+    /// never from the cache of an earlier lookup
+    pub async fn direct_ip_lookup(&self, host: &str, ip_type: IpType) -> Result<IpAddr, DnsError> {
+        let name = host.into_name().map_err(|e| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e.into())),
+        })?;
+        let record_type = match ip_type {
+            IpType::V4 => RecordType::A,
+            IpType::V6 => RecordType::AAAA,
+        };
+
+        let response = self
+            .direct_lookup(host, Query::query(name, record_type))
+            .await?;
+        response
+            .answers()
+            .iter()
+            .find_map(|record| match record.data() {
+                RData::A(a) => Some(IpAddr::V4(a.0)),
+                RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            })
+            .ok_or_else(|| DnsError {
+                kind: Box::new(DnsErrorKind::UnexpectedResponse(0)),
+            })
+    }
+
+    /// Looks up TXT records in the CHAOS class, where servers answer questions about themselves
+    pub async fn chaos_txt_lookup(&self, host: &str) -> Result<Vec<String>, DnsError> {
+        let to_err = |e: ResolveError| DnsError {
+            kind: Box::new(DnsErrorKind::DnsResolve(e)),
+        };
+
+        let name = host.into_name().map_err(|e| to_err(e.into()))?;
+        let mut query = Query::query(name, RecordType::TXT);
+        query.set_query_class(DNSClass::CH);
+
+        let response = self.direct_lookup(host, query).await?;
+
+        Ok(response
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                RData::TXT(txt) => Some(txt.to_string()),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+impl DnsResolver {
+    /// A resolver that asks a local server, which answers every address query of the type with the
+    /// address and finds nothing for anything else, so that tests don't depend on the internet
+    pub async fn for_test(answer: IpAddr) -> Self {
+        use hickory_resolver::proto::op::{Message, MessageType};
+        use hickory_resolver::proto::rr::rdata::{A, AAAA};
+        use hickory_resolver::proto::rr::Record;
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code())
+                    .set_recursion_available(true);
+
+                for query in request.queries() {
+                    response.add_query(query.clone());
+                    let data = match (query.query_type(), answer) {
+                        (RecordType::A, IpAddr::V4(ip)) => RData::A(A(ip)),
+                        (RecordType::AAAA, IpAddr::V6(ip)) => RData::AAAA(AAAA(ip)),
+                        _ => continue,
+                    };
+                    response.add_answer(Record::from_rdata(query.name().clone(), 300, data));
+                }
+
+                let bytes = response.to_vec().unwrap();
+                socket.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+
+        let local = [IpAddr::V4(Ipv4Addr::LOCALHOST)];
+        let servers = NameServerConfigGroup::from_ips_clear(&local, port, false);
+        let config = ResolverConfig::from_parts(None, vec![], servers);
+        Self::from_config(config).await.unwrap()
+    }
 }
 
 #[derive(Debug)]
-struct OpenDnsResolver {
+pub struct OpenDnsResolver {
     resolver: DnsResolver,
     ip_type: IpType,
 }
 
 impl OpenDnsResolver {
-    async fn create(ip_type: IpType) -> Result<Self, DnsError> {
-        let resolver = DnsResolver::create_opendns(ip_type).await?;
+    pub async fn create(ip_type: IpType, transport: DnsTransport) -> Result<Self, DnsError> {
+        let resolver = DnsResolver::create_opendns(ip_type, transport).await?;
         Ok(OpenDnsResolver { resolver, ip_type })
     }
 
-    async fn wan_lookup(&self) -> Result<IpAddr, DnsError> {
+    pub fn with_retry(self, retry: &RetryConfig) -> Self {
+        OpenDnsResolver {
+            resolver: self.resolver.with_retry(retry),
+            ..self
+        }
+    }
+
+    pub async fn wan_lookup(&self) -> Result<IpAddr, DnsError> {
         const DOMAIN: &str = "myip.opendns.com.";
         match self.ip_type {
             IpType::V4 => self.resolver.ipv4_lookup(DOMAIN).await.map(Into::into),
@@ -114,15 +336,139 @@ impl OpenDnsResolver {
     }
 }
 
-pub async fn wan_lookup_ip(ip_type: IpType) -> Result<IpAddr, DnsError> {
-    let opendns = OpenDnsResolver::create(ip_type).await?;
-    opendns.wan_lookup().await
+/// Services that reply to a DNS query with the address the query came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhoamiService {
+    /// A CHAOS class TXT query of "whoami.cloudflare" against 1.1.1.1
+    Cloudflare,
+
+    /// A TXT query of "o-o.myaddr.l.google.com" against Google's authoritative servers
+    Google,
+
+    /// An A query of "whoami.akamai.net" against Akamai's authoritative servers. IPv4 only.
+    Akamai,
+}
+
+impl WhoamiService {
+    /// The name to verify the server's certificate against over an encrypted transport. Only
+    /// Cloudflare's resolver has one, as the other services answer from their authoritative name
+    /// servers, which only speak udp.
+    pub fn tls_name(self) -> Option<&'static str> {
+        match self {
+            WhoamiService::Cloudflare => Some("cloudflare-dns.com"),
+            WhoamiService::Google | WhoamiService::Akamai => None,
+        }
+    }
+
+    fn nameservers(self, ip_type: IpType) -> Option<[IpAddr; 2]> {
+        match (self, ip_type) {
+            (WhoamiService::Cloudflare, IpType::V4) => Some([
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)),
+            ]),
+            (WhoamiService::Cloudflare, IpType::V6) => Some([
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1001)),
+            ]),
+            // ns1.google.com and ns2.google.com
+            (WhoamiService::Google, IpType::V4) => Some([
+                IpAddr::V4(Ipv4Addr::new(216, 239, 32, 10)),
+                IpAddr::V4(Ipv4Addr::new(216, 239, 34, 10)),
+            ]),
+            (WhoamiService::Google, IpType::V6) => Some([
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4802, 0x32, 0, 0, 0, 0xa)),
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4802, 0x34, 0, 0, 0, 0xa)),
+            ]),
+            // ns1-1.akamaitech.net and ns1-2.akamaitech.net
+            (WhoamiService::Akamai, IpType::V4) => Some([
+                IpAddr::V4(Ipv4Addr::new(193, 108, 88, 1)),
+                IpAddr::V4(Ipv4Addr::new(193, 108, 89, 1)),
+            ]),
+            (WhoamiService::Akamai, IpType::V6) => None,
+        }
+    }
+}
+
+/// Resolves the WAN IP by asking a whoami service over DNS, with a resolver per address type
+#[derive(Debug)]
+pub struct WhoamiResolver {
+    service: WhoamiService,
+    v4: DnsResolver,
+    v6: Option<DnsResolver>,
+}
+
+impl WhoamiResolver {
+    /// Services without a TLS name are always asked over udp, whatever the transport
+    pub async fn create(service: WhoamiService, transport: DnsTransport) -> Result<Self, DnsError> {
+        let (transport, tls_name) = match service.tls_name() {
+            Some(tls_name) => (transport, tls_name),
+            None => (DnsTransport::Udp, ""),
+        };
+
+        let v4 = match service.nameservers(IpType::V4) {
+            Some(ips) => DnsResolver::from_servers(&ips, transport, tls_name).await?,
+            None => {
+                return Err(DnsError {
+                    kind: Box::new(DnsErrorKind::UnsupportedIpType(IpType::V4)),
+                })
+            }
+        };
+
+        let v6 = match service.nameservers(IpType::V6) {
+            Some(ips) => Some(DnsResolver::from_servers(&ips, transport, tls_name).await?),
+            None => None,
+        };
+
+        Ok(WhoamiResolver { service, v4, v6 })
+    }
+
+    pub fn with_retry(self, retry: &RetryConfig) -> Self {
+        WhoamiResolver {
+            v4: self.v4.with_retry(retry),
+            v6: self.v6.map(|v6| v6.with_retry(retry)),
+            ..self
+        }
+    }
+
+    pub async fn wan_lookup(&self, ip_type: IpType) -> Result<IpAddr, DnsError> {
+        let resolver = match ip_type {
+            IpType::V4 => &self.v4,
+            IpType::V6 => self.v6.as_ref().ok_or_else(|| DnsError {
+                kind: Box::new(DnsErrorKind::UnsupportedIpType(ip_type)),
+            })?,
+        };
+
+        let txts = match self.service {
+            WhoamiService::Cloudflare => resolver.chaos_txt_lookup("whoami.cloudflare.").await?,
+            WhoamiService::Google => resolver.txt_lookup("o-o.myaddr.l.google.com.").await?,
+            WhoamiService::Akamai => {
+                return resolver.ip_lookup("whoami.akamai.net.", ip_type).await
+            }
+        };
+
+        parse_txt_addr(&txts, ip_type).ok_or_else(|| DnsError {
+            kind: Box::new(DnsErrorKind::UnexpectedResponse(txts.len())),
+        })
+    }
+}
+
+/// The first TXT record that holds an address of the requested type. Google, when queried
+/// through a recursive resolver, may also reply with an "edns0-client-subnet" record.
+fn parse_txt_addr(txts: &[String], ip_type: IpType) -> Option<IpAddr> {
+    txts.iter()
+        .filter_map(|txt| txt.trim_matches('"').trim().parse::<IpAddr>().ok())
+        .find(|addr| IpType::from(*addr) == ip_type)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    async fn wan_lookup_ip(ip_type: IpType) -> Result<IpAddr, DnsError> {
+        let opendns = OpenDnsResolver::create(ip_type, DnsTransport::Udp).await?;
+        opendns.wan_lookup().await
+    }
+
     #[tokio::test]
     async fn opendns_lookup_ipv4_test() {
         // Heads up: this test requires internet connectivity
@@ -143,7 +489,9 @@ mod tests {
                         }
                         panic!("unexpected DNS error: {}", e);
                     }
-                    DnsErrorKind::UnexpectedResponse(_) => {
+                    DnsErrorKind::UnexpectedResponse(_)
+                    | DnsErrorKind::UnsupportedIpType(_)
+                    | DnsErrorKind::NoNameServers(_) => {
                         panic!("unexpected response: {}", e);
                     }
                 }
@@ -172,7 +520,9 @@ mod tests {
                         }
                         panic!("unexpected DNS error: {}", e);
                     }
-                    DnsErrorKind::UnexpectedResponse(_) => {
+                    DnsErrorKind::UnexpectedResponse(_)
+                    | DnsErrorKind::UnsupportedIpType(_)
+                    | DnsErrorKind::NoNameServers(_) => {
                         panic!("unexpected response: {}", e);
                     }
                 }
@@ -180,19 +530,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_whoami_txt() {
+        let txts = vec![
+            String::from("edns0-client-subnet 203.0.113.0/24"),
+            String::from("198.51.100.7"),
+        ];
+        assert_eq!(
+            parse_txt_addr(&txts, IpType::V4),
+            Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)))
+        );
+        assert_eq!(parse_txt_addr(&txts, IpType::V6), None);
+
+        let txts = vec![String::from("\"2001:db8::7\"")];
+        assert_eq!(
+            parse_txt_addr(&txts, IpType::V6),
+            Some("2001:db8::7".parse().unwrap())
+        );
+    }
+
     #[tokio::test]
+    #[ignore = "requires internet connectivity"]
     async fn cloudflare_lookup_ipv4_test() {
-        // Heads up: this test requires internet connectivity
-        let resolver = DnsResolver::create_cloudflare().await.unwrap();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
         let ip = resolver.ipv4_lookup("example.com.").await.unwrap();
         assert!(!ip.is_loopback());
     }
 
+    #[tokio::test]
+    async fn local_lookup() {
+        let answer = Ipv4Addr::new(192, 0, 2, 7);
+        let resolver = DnsResolver::for_test(IpAddr::V4(answer)).await;
+        assert_eq!(resolver.ipv4_lookup("example.com.").await.unwrap(), answer);
+
+        let err = resolver.ipv6_lookup("example.com.").await.unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+    }
+
     #[tokio::test]
     #[ignore] // GitHub runner doesn't have IPv6 internet connectivity
     async fn cloudflare_lookup_ipv6_test() {
         // Heads up: this test requires internet connectivity
-        let resolver = DnsResolver::create_cloudflare().await.unwrap();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
         let ip = resolver.ipv6_lookup("example.com.").await.unwrap();
         assert!(!ip.is_loopback());
     }
@@ -1,13 +1,15 @@
-use crate::config::DynuConfig;
-use crate::core::Updates;
-use crate::dns::DnsResolver;
+use crate::authoritative;
+use crate::config::{DynuConfig, IpType, RetryConfig};
+use crate::core::{fqdn, Context, Updates};
 use crate::errors::DnessError;
+use crate::retry::SendRetry;
 use log::{info, warn};
 use std::net::IpAddr;
 
 #[derive(Debug)]
 pub struct DynuProvider<'a> {
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
     config: &'a DynuConfig,
 }
 
@@ -40,7 +42,7 @@ impl DynuProvider<'_> {
                 self.config.username.clone(),
                 Some(self.config.password.clone()),
             )
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&get_url, "dynu update", e))?
             .error_for_status()
@@ -60,31 +62,56 @@ impl DynuProvider<'_> {
     }
 }
 
+/// Returns the configured records that can't be resolved. The credentials can't be verified as the
+/// update endpoint is the only authenticated api.
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &DynuConfig,
+    ip_type: IpType,
+) -> Vec<String> {
+    let mut missing = Vec::new();
+    for record in &config.records {
+        let dns_query = format!("{}.", fqdn(record, &config.hostname));
+        if ctx.resolver.ip_lookup(&dns_query, ip_type).await.is_err() {
+            missing.push(record.clone());
+        }
+    }
+    missing
+}
+
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &DynuConfig,
     wan: IpAddr,
 ) -> Result<Updates, DnessError> {
-    let resolver = DnsResolver::create_cloudflare().await?;
-    let dynu_provider = DynuProvider { client, config };
+    let dynu_provider = DynuProvider {
+        client: ctx.http,
+        retry: ctx.retry,
+        config,
+    };
 
     let mut results = Updates::default();
+    let mut updated = Vec::new();
 
     for record in &config.records {
-        let dns_query = if record == "@" {
-            format!("{}.", config.hostname)
-        } else {
-            format!("{}.{}.", record, config.hostname)
-        };
-
-        let response = resolver.ip_lookup(&dns_query, wan.into()).await;
+        let host = fqdn(record, &config.hostname);
+        let dns_query = format!("{}.", host);
+        let response = authoritative::current_ip(ctx, &dns_query, wan).await;
 
         match response {
             Ok(ip) => {
                 if ip == wan {
                     results.current += 1;
+                    ctx.changes.current(&host);
+                } else if ctx.dry_run {
+                    ctx.changes
+                        .plan(&host, &config.hostname, &ip.to_string(), wan);
+                    results.updated += 1;
                 } else {
                     dynu_provider.update_domain(record, wan).await?;
+                    updated.push(host.clone());
+                    ctx.changes
+                        .push(&host, &config.hostname, &ip.to_string(), wan);
                     info!(
                         "{} from domain {} updated from {} to {}",
                         record, config.hostname, ip, wan
@@ -92,24 +119,27 @@ pub async fn update_domains(
                     results.updated += 1;
                 }
             }
+            Err(e) if !e.is_not_found() => return Err(e.into()),
             Err(e) => {
-                // Could be a network issue or it could be that the record didn't exist.
                 warn!(
                     "resolving dynu record ({}) encountered an error: {}",
                     record, e
                 );
                 results.missing += 1;
+                ctx.changes.missing(&host);
             }
         }
     }
 
+    authoritative::verify(ctx, &updated, wan).await?;
     Ok(results)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::IpType;
+    use crate::dns::DnsResolver;
+    use std::collections::HashMap;
     use std::net::Ipv4Addr;
 
     macro_rules! dynu_server {
@@ -139,6 +169,9 @@ mod tests {
     async fn test_dynu_update() {
         let (tx, addr) = dynu_server!();
         let http_client = reqwest::Client::new();
+        let old_ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let resolver = DnsResolver::for_test(old_ip).await;
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = DynuConfig {
             base_url: format!("http://{}", addr),
@@ -147,9 +180,13 @@ mod tests {
             password: String::from("secret-1"),
             records: vec![String::from("@")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
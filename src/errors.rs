@@ -1,6 +1,25 @@
+use crate::config::IpType;
+use crate::interface::InterfaceError;
+use crate::stun::StunError;
+use hickory_resolver::ResolveError;
 use std::error;
 use std::fmt;
-use hickory_resolver::ResolveError;
+
+/// The context followed by the error and each of its causes
+pub fn error_chain(context: &str, err: &dyn error::Error) -> Vec<String> {
+    let mut chain = vec![String::from(context), err.to_string()];
+    let mut ie = err.source();
+    while let Some(cause) = ie {
+        chain.push(cause.to_string());
+        ie = cause.source();
+    }
+    chain
+}
+
+/// Flattens an error and its causes into a single line
+pub fn describe_error(context: &str, err: &dyn error::Error) -> String {
+    error_chain(context, err).join(": ")
+}
 
 #[derive(Debug)]
 pub enum DnessErrorKind {
@@ -20,9 +39,23 @@ pub enum DnessErrorKind {
         source: reqwest::Error,
     },
     Message(String),
+    DnsUpdate {
+        server: String,
+        context: String,
+        reason: String,
+    },
     Dns {
         source: DnsError,
     },
+    Interface {
+        source: InterfaceError,
+    },
+    Stun {
+        source: StunError,
+    },
+    Smtp {
+        source: lettre::transport::smtp::Error,
+    },
 }
 
 #[derive(Debug)]
@@ -66,6 +99,31 @@ impl DnessError {
             kind: DnessErrorKind::Message(msg),
         }
     }
+
+    pub fn dns_update(server: &str, context: &str, reason: String) -> DnessError {
+        DnessError {
+            kind: DnessErrorKind::DnsUpdate {
+                server: String::from(server),
+                context: String::from(context),
+                reason,
+            },
+        }
+    }
+
+    /// A short name for the kind of error, used as a metrics label
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            DnessErrorKind::SendHttp { .. } => "send_http",
+            DnessErrorKind::BadResponse { .. } => "bad_response",
+            DnessErrorKind::Deserialize { .. } => "deserialize",
+            DnessErrorKind::Message(_) => "message",
+            DnessErrorKind::DnsUpdate { .. } => "dns_update",
+            DnessErrorKind::Dns { .. } => "dns",
+            DnessErrorKind::Interface { .. } => "interface",
+            DnessErrorKind::Stun { .. } => "stun",
+            DnessErrorKind::Smtp { .. } => "smtp",
+        }
+    }
 }
 
 impl From<DnsError> for DnessError {
@@ -76,6 +134,30 @@ impl From<DnsError> for DnessError {
     }
 }
 
+impl From<InterfaceError> for DnessError {
+    fn from(source: InterfaceError) -> Self {
+        DnessError {
+            kind: DnessErrorKind::Interface { source },
+        }
+    }
+}
+
+impl From<StunError> for DnessError {
+    fn from(source: StunError) -> Self {
+        DnessError {
+            kind: DnessErrorKind::Stun { source },
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for DnessError {
+    fn from(source: lettre::transport::smtp::Error) -> Self {
+        DnessError {
+            kind: DnessErrorKind::Smtp { source },
+        }
+    }
+}
+
 impl error::Error for DnessError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self.kind {
@@ -83,6 +165,9 @@ impl error::Error for DnessError {
             DnessErrorKind::BadResponse { ref source, .. } => Some(source),
             DnessErrorKind::Deserialize { ref source, .. } => Some(source),
             DnessErrorKind::Dns { ref source, .. } => Some(source),
+            DnessErrorKind::Interface { ref source, .. } => Some(source),
+            DnessErrorKind::Stun { ref source, .. } => Some(source),
+            DnessErrorKind::Smtp { ref source, .. } => Some(source),
             _ => None,
         }
     }
@@ -106,7 +191,15 @@ impl fmt::Display for DnessError {
                 "unable to deserialize response for {}: url attempted: {}",
                 context, url
             ),
+            DnessErrorKind::DnsUpdate {
+                server,
+                context,
+                reason,
+            } => write!(f, "{} with {} failed: {}", context, server, reason),
             DnessErrorKind::Dns { .. } => write!(f, "dns lookup"),
+            DnessErrorKind::Interface { .. } => write!(f, "interface lookup"),
+            DnessErrorKind::Stun { .. } => write!(f, "stun lookup"),
+            DnessErrorKind::Smtp { .. } => write!(f, "sending email"),
             DnessErrorKind::Message(msg) => write!(f, "{}", msg),
         }
     }
@@ -121,6 +214,20 @@ pub struct DnsError {
 pub enum DnsErrorKind {
     DnsResolve(ResolveError),
     UnexpectedResponse(usize),
+    UnsupportedIpType(IpType),
+    NoNameServers(String),
+}
+
+impl DnsError {
+    /// Whether the name exists without records of the type, or doesn't exist at all, as opposed
+    /// to the lookup failing
+    pub fn is_not_found(&self) -> bool {
+        match &*self.kind {
+            DnsErrorKind::DnsResolve(e) => e.is_no_records_found(),
+            DnsErrorKind::UnexpectedResponse(0) => true,
+            _ => false,
+        }
+    }
 }
 
 impl error::Error for DnsError {
@@ -128,6 +235,8 @@ impl error::Error for DnsError {
         match *self.kind {
             DnsErrorKind::DnsResolve(ref e) => Some(e),
             DnsErrorKind::UnexpectedResponse(_) => None,
+            DnsErrorKind::UnsupportedIpType(_) => None,
+            DnsErrorKind::NoNameServers(_) => None,
         }
     }
 }
@@ -139,6 +248,16 @@ impl fmt::Display for DnsError {
             DnsErrorKind::UnexpectedResponse(results) => {
                 write!(f, "unexpected number of results: {}", results)
             }
+            DnsErrorKind::UnsupportedIpType(ip_type) => {
+                write!(f, "{} records are not supported", ip_type.record_type())
+            }
+            DnsErrorKind::NoNameServers(host) => {
+                write!(
+                    f,
+                    "could not find the authoritative name servers of {}",
+                    host
+                )
+            }
         }
     }
 }
@@ -0,0 +1,465 @@
+use crate::config::{IpType, NatPmpResolverConfig, UpnpResolverConfig};
+use crate::errors::DnessError;
+use log::{debug, warn};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+const IGD_DEVICE: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+// Services that can report the external address, in order of preference
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+const NATPMP_PORT: u16 = 5351;
+
+// RFC 6886 starts retransmitting after 250ms, doubling each time
+const NATPMP_ATTEMPTS: u32 = 4;
+const NATPMP_TIMEOUT: Duration = Duration::from_millis(250);
+
+fn only_ipv4(resolver: &str, ip_type: IpType) -> Result<(), DnessError> {
+    match ip_type {
+        IpType::V4 => Ok(()),
+        IpType::V6 => Err(DnessError::message(format!(
+            "the {} ip resolver only supports A records",
+            resolver
+        ))),
+    }
+}
+
+/// Whether the address is one that isn't reachable from the internet: a private (RFC 1918) or
+/// shared carrier-grade NAT (100.64.0.0/10) address
+fn is_private(addr: Ipv4Addr) -> bool {
+    let octets = addr.octets();
+    addr.is_private() || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+}
+
+fn warn_if_private(resolver: &str, addr: Ipv4Addr) {
+    if is_private(addr) {
+        warn!(
+            "{} reports the router's external address as {}, which is private or CGNAT. The router is likely behind another NAT.",
+            resolver, addr
+        );
+    }
+}
+
+/// The inner text of the first element with the given tag name
+fn xml_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(xml[start..end].trim())
+}
+
+/// The inner text of the first element with the given name in any namespace
+fn xml_text_suffix<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let qualified = format!(":{}", name);
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        rest = &rest[lt + 1..];
+        let gt = rest.find('>')?;
+        let tag = rest[..gt].split_whitespace().next().unwrap_or_default();
+        if tag == name || tag.ends_with(&qualified) {
+            let body = &rest[gt + 1..];
+            return body.find("</").map(|end| body[..end].trim());
+        }
+    }
+    None
+}
+
+/// Reads the LOCATION header, the url of the device description, out of an SSDP response
+fn parse_ssdp_location(response: &str) -> Option<&str> {
+    response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("location") {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Finds the service type and control url of a WAN connection service in the device description
+fn parse_wan_service(description: &str) -> Option<(&'static str, &str)> {
+    let services: Vec<&str> = description.split("<service>").skip(1).collect();
+    WAN_SERVICES.iter().find_map(|service_type| {
+        services.iter().find_map(|service| {
+            if xml_text(service, "serviceType")? == *service_type {
+                Some((*service_type, xml_text(service, "controlURL")?))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+async fn ssdp_discover() -> Result<String, DnessError> {
+    let to_err = |e: std::io::Error| DnessError::message(format!("upnp discovery failed: {}", e));
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(to_err)?;
+
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\r\n",
+        SSDP_ADDR, IGD_DEVICE
+    );
+    socket
+        .send_to(request.as_bytes(), SSDP_ADDR)
+        .await
+        .map_err(to_err)?;
+
+    let mut buf = [0u8; 2048];
+    let discover = async {
+        loop {
+            let (received, from) = socket.recv_from(&mut buf).await?;
+            let response = String::from_utf8_lossy(&buf[..received]);
+            if let Some(location) = parse_ssdp_location(&response) {
+                debug!("found internet gateway device at {}: {}", from, location);
+                return Ok(String::from(location));
+            }
+        }
+    };
+
+    match tokio::time::timeout(SSDP_TIMEOUT, discover).await {
+        Ok(location) => location.map_err(to_err),
+        Err(_) => Err(DnessError::message(String::from(
+            "no internet gateway device responded to upnp discovery",
+        ))),
+    }
+}
+
+/// Asks the router for its external address over UPnP IGD
+pub async fn upnp_addr(
+    client: &reqwest::Client,
+    config: &UpnpResolverConfig,
+    ip_type: IpType,
+) -> Result<IpAddr, DnessError> {
+    only_ipv4("upnp", ip_type)?;
+
+    let location = match &config.location {
+        Some(location) => location.clone(),
+        None => ssdp_discover().await?,
+    };
+
+    let description = client
+        .get(&location)
+        .send()
+        .await
+        .map_err(|e| DnessError::send_http(&location, "upnp device description", e))?
+        .error_for_status()
+        .map_err(|e| DnessError::bad_response(&location, "upnp device description", e))?
+        .text()
+        .await
+        .map_err(|e| DnessError::deserialize(&location, "upnp device description", e))?;
+
+    let (service_type, control_url) = parse_wan_service(&description).ok_or_else(|| {
+        DnessError::message(format!("no wan connection service found at {}", location))
+    })?;
+
+    // Control urls are typically relative to the description, unless a base is given
+    let base = xml_text(&description, "URLBase").unwrap_or(&location);
+    let control_url = reqwest::Url::parse(base)
+        .and_then(|x| x.join(control_url))
+        .map_err(|e| DnessError::message(format!("invalid upnp control url: {}", e)))?;
+
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+<s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"></u:GetExternalIPAddress></s:Body>\
+</s:Envelope>",
+        service_type
+    );
+
+    let url = control_url.as_str();
+    let response = client
+        .post(control_url.clone())
+        .header("Content-Type", "text/xml; charset=\"utf-8\"")
+        .header(
+            "SOAPAction",
+            format!("\"{}#GetExternalIPAddress\"", service_type),
+        )
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| DnessError::send_http(url, "upnp get external ip", e))?
+        .error_for_status()
+        .map_err(|e| DnessError::bad_response(url, "upnp get external ip", e))?
+        .text()
+        .await
+        .map_err(|e| DnessError::deserialize(url, "upnp get external ip", e))?;
+
+    let ip_text = xml_text_suffix(&response, "NewExternalIPAddress").ok_or_else(|| {
+        DnessError::message(format!(
+            "no external address in upnp response: {}",
+            response
+        ))
+    })?;
+
+    let ip = ip_text.parse::<Ipv4Addr>().map_err(|_| {
+        DnessError::message(format!("unable to parse {} as an ipv4 address", ip_text))
+    })?;
+
+    warn_if_private("upnp", ip);
+    Ok(IpAddr::V4(ip))
+}
+
+/// Parses /proc/net/route for the gateway of the default route. Addresses are written as the
+/// hex of their in-memory (little endian) representation.
+fn parse_default_gateway(routes: &str) -> Option<Ipv4Addr> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if *fields.get(1)? != "00000000" {
+            return None;
+        }
+
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_le_bytes())).filter(|x| !x.is_unspecified())
+    })
+}
+
+fn natpmp_gateway(config: &NatPmpResolverConfig) -> Result<SocketAddr, DnessError> {
+    match &config.gateway {
+        Some(gateway) => gateway
+            .parse::<SocketAddr>()
+            .or_else(|_| {
+                gateway
+                    .parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, NATPMP_PORT))
+            })
+            .map_err(|_| DnessError::message(format!("invalid nat-pmp gateway: {}", gateway))),
+        None => std::fs::read_to_string("/proc/net/route")
+            .ok()
+            .and_then(|x| parse_default_gateway(&x))
+            .map(|ip| SocketAddr::new(IpAddr::V4(ip), NATPMP_PORT))
+            .ok_or_else(|| {
+                DnessError::message(String::from(
+                    "unable to find the default gateway, configure it in [resolvers.natpmp]",
+                ))
+            }),
+    }
+}
+
+/// Reads the external address out of a NAT-PMP response: the version, the opcode (128 + 0), the
+/// result code, the seconds since the mapping table was reset, and the address
+fn parse_natpmp_response(response: &[u8]) -> Result<Ipv4Addr, String> {
+    match response {
+        [0, 128, result_hi, result_lo, _, _, _, _, a, b, c, d, ..] => {
+            match u16::from_be_bytes([*result_hi, *result_lo]) {
+                0 => Ok(Ipv4Addr::new(*a, *b, *c, *d)),
+                code => Err(format!("gateway responded with result code {}", code)),
+            }
+        }
+
+        // PCP (version 2) gateways that don't also speak NAT-PMP reply with their own version
+        [version, ..] if *version != 0 => Err(format!(
+            "gateway only speaks version {} of the protocol (pcp), which isn't supported",
+            version
+        )),
+        _ => Err(String::from("malformed response")),
+    }
+}
+
+/// Asks the router for its external address with a NAT-PMP external address request. PCP isn't
+/// spoken, so a gateway that only supports PCP answers with an unsupported version error.
+pub async fn natpmp_addr(
+    config: &NatPmpResolverConfig,
+    ip_type: IpType,
+) -> Result<IpAddr, DnessError> {
+    only_ipv4("natpmp", ip_type)?;
+
+    let gateway = natpmp_gateway(config)?;
+    let to_err = |e: std::io::Error| {
+        DnessError::message(format!("nat-pmp request to {} failed: {}", gateway, e))
+    };
+
+    let bind: SocketAddr = match gateway {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await.map_err(to_err)?;
+    socket.connect(gateway).await.map_err(to_err)?;
+
+    let mut buf = [0u8; 16];
+    let mut timeout = NATPMP_TIMEOUT;
+    for attempt in 1..=NATPMP_ATTEMPTS {
+        socket.send(&[0, 0]).await.map_err(to_err)?;
+        match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+            Ok(received) => {
+                let received = received.map_err(to_err)?;
+                let ip = parse_natpmp_response(&buf[..received]).map_err(|e| {
+                    DnessError::message(format!("nat-pmp request to {}: {}", gateway, e))
+                })?;
+                warn_if_private("natpmp", ip);
+                return Ok(IpAddr::V4(ip));
+            }
+            Err(_) => debug!("no nat-pmp response from {} (attempt {})", gateway, attempt),
+        }
+        timeout *= 2;
+    }
+
+    Err(DnessError::message(format!(
+        "no nat-pmp response from {}",
+        gateway
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+    </serviceList>
+    <deviceList>
+      <device>
+        <serviceList>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+            <controlURL>/ctl/IPConn</controlURL>
+          </service>
+        </serviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+    const SOAP_RESPONSE: &str = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body><u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalIPAddress>198.51.100.7</NewExternalIPAddress>
+</u:GetExternalIPAddressResponse></s:Body></s:Envelope>"#;
+
+    #[test]
+    fn private_addresses() {
+        assert!(is_private(Ipv4Addr::new(192, 168, 1, 2)));
+        assert!(is_private(Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(is_private(Ipv4Addr::new(100, 64, 0, 1)));
+        assert!(is_private(Ipv4Addr::new(100, 127, 255, 254)));
+        assert!(!is_private(Ipv4Addr::new(100, 128, 0, 1)));
+        assert!(!is_private(Ipv4Addr::new(198, 51, 100, 7)));
+    }
+
+    #[test]
+    fn parse_ssdp() {
+        let response = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(
+            parse_ssdp_location(response),
+            Some("http://192.168.1.1:5000/rootDesc.xml")
+        );
+    }
+
+    #[test]
+    fn parse_description() {
+        assert_eq!(
+            parse_wan_service(DESCRIPTION),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "/ctl/IPConn"
+            ))
+        );
+        assert_eq!(
+            xml_text_suffix(SOAP_RESPONSE, "NewExternalIPAddress"),
+            Some("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn parse_route_table() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0
+";
+        assert_eq!(
+            parse_default_gateway(routes),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+    }
+
+    #[test]
+    fn parse_natpmp() {
+        let response = [0, 128, 0, 0, 0, 0, 0x1c, 0x20, 100, 64, 0, 9];
+        assert_eq!(
+            parse_natpmp_response(&response),
+            Ok(Ipv4Addr::new(100, 64, 0, 9))
+        );
+        assert!(parse_natpmp_response(&[0, 128, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        let pcp_only = parse_natpmp_response(&[2, 128, 0, 1]).unwrap_err();
+        assert!(
+            pcp_only.contains("(pcp), which isn't supported"),
+            "{}",
+            pcp_only
+        );
+    }
+
+    #[tokio::test]
+    async fn natpmp_local_gateway() {
+        let gateway = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = gateway.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 16];
+            let (received, from) = gateway.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..received], &[0, 0]);
+            let response = [0, 128, 0, 0, 0, 0, 0x1c, 0x20, 198, 51, 100, 7];
+            gateway.send_to(&response, from).unwrap();
+        });
+
+        let config = NatPmpResolverConfig {
+            gateway: Some(addr.to_string()),
+        };
+        let ip = natpmp_addr(&config, IpType::V4).await.unwrap();
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        assert!(natpmp_addr(&config, IpType::V6).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn upnp_local_gateway() {
+        use rouille::Response;
+        use rouille::Server;
+
+        let server = Server::new("localhost:0", |request| {
+            match (request.method(), request.url().as_str()) {
+                ("GET", "/rootDesc.xml") => Response::from_data("text/xml", DESCRIPTION),
+                ("POST", "/ctl/IPConn") => match request.header("SOAPAction") {
+                    Some(
+                        "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+                    ) => Response::from_data("text/xml", SOAP_RESPONSE),
+                    _ => Response::text("bad soap action").with_status_code(500),
+                },
+                _ => Response::empty_404(),
+            }
+        })
+        .unwrap();
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let addr = server.server_addr();
+        std::thread::spawn(move || {
+            while rx.try_recv().is_err() {
+                server.poll();
+                std::thread::sleep(std::time::Duration::from_millis(50))
+            }
+        });
+
+        let config = UpnpResolverConfig {
+            location: Some(format!("http://{}/rootDesc.xml", addr)),
+        };
+        let client = reqwest::Client::new();
+        let ip = upnp_addr(&client, &config, IpType::V4).await;
+        tx.send(()).unwrap();
+
+        assert_eq!(ip.unwrap(), IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+    }
+}
@@ -1,7 +1,8 @@
-use crate::config::GoDaddyConfig;
 use crate::config::IpType;
-use crate::core::Updates;
+use crate::config::{GoDaddyConfig, RetryConfig};
+use crate::core::{ChangeLog, Context, Updates};
 use crate::errors::DnessError;
+use crate::retry::SendRetry;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -26,16 +27,48 @@ struct GoClient<'a> {
     secret: String,
     records: HashSet<String>,
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
+    create_ttl: Option<u32>,
+    dry_run: bool,
+    changes: &'a ChangeLog,
 }
 
 impl GoClient<'_> {
-    fn log_missing_domains(&self, remote_domains: &[GoRecord]) -> usize {
-        let actual = remote_domains
+    fn create<'b>(ctx: &Context<'b>, config: &GoDaddyConfig) -> GoClient<'b> {
+        GoClient {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            domain: config.domain.clone(),
+            key: config.key.clone(),
+            secret: config.secret.clone(),
+            records: config.records.iter().cloned().collect(),
+            client: ctx.http,
+            retry: ctx.retry,
+            create_ttl: Some(config.create_ttl).filter(|_| config.create_missing),
+            dry_run: ctx.dry_run,
+            changes: ctx.changes,
+        }
+    }
+
+    fn remote_names(remote_domains: &[GoRecord]) -> HashSet<String> {
+        remote_domains
             .iter()
             .map(|x| &x.name)
             .cloned()
-            .collect::<HashSet<String>>();
-        crate::core::log_missing_domains(&self.records, &actual, "GoDaddy", &self.domain)
+            .collect::<HashSet<String>>()
+    }
+
+    fn log_missing_domains(&self, remote_domains: &[GoRecord]) -> usize {
+        let actual = Self::remote_names(remote_domains);
+        let missing =
+            crate::core::log_missing_domains(&self.records, &actual, "GoDaddy", &self.domain);
+        for name in &missing {
+            self.changes.missing(&crate::core::fqdn(name, &self.domain));
+        }
+        missing.len()
+    }
+
+    fn missing_domains(&self, remote_domains: &[GoRecord]) -> Vec<String> {
+        crate::core::missing_records(&self.records, &Self::remote_names(remote_domains))
     }
 
     fn auth_header(&self) -> String {
@@ -53,7 +86,7 @@ impl GoClient<'_> {
             .client
             .get(&get_url)
             .header("Authorization", self.auth_header())
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&get_url, "godaddy fetch records", e))?
             .error_for_status()
@@ -80,7 +113,7 @@ impl GoClient<'_> {
                 data: addr.to_string(),
                 ..record.clone()
             }])
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&put_url, "godaddy update records", e))?
             .error_for_status()
@@ -89,6 +122,59 @@ impl GoClient<'_> {
         Ok(())
     }
 
+    async fn create_record(&self, name: &str, addr: IpAddr, ttl: u32) -> Result<(), DnessError> {
+        let patch_url = format!("{}/v1/domains/{}/records", self.base_url, self.domain);
+
+        let mut other = Map::new();
+        other.insert(String::from("ttl"), Value::from(ttl));
+        other.insert(
+            String::from("type"),
+            Value::from(IpType::from(addr).record_type()),
+        );
+
+        self.client
+            .patch(&patch_url)
+            .header("Authorization", self.auth_header())
+            .json(&vec![GoRecord {
+                data: addr.to_string(),
+                name: name.to_string(),
+                other,
+            }])
+            .send_retry_non_idempotent(self.retry)
+            .await
+            .map_err(|e| DnessError::send_http(&patch_url, "godaddy create records", e))?
+            .error_for_status()
+            .map_err(|e| DnessError::bad_response(&patch_url, "godaddy create records", e))?;
+
+        Ok(())
+    }
+
+    /// Creates the configured records that don't exist yet
+    async fn create_missing(
+        &self,
+        remote_domains: &[GoRecord],
+        addr: IpAddr,
+        ttl: u32,
+    ) -> Result<Updates, DnessError> {
+        let missing = self.missing_domains(remote_domains);
+        for name in &missing {
+            if self.dry_run {
+                let fqdn = crate::core::fqdn(name, &self.domain);
+                self.changes.plan(&fqdn, &self.domain, "", addr);
+            } else {
+                self.create_record(name, addr, ttl).await?;
+                let fqdn = crate::core::fqdn(name, &self.domain);
+                self.changes.push(&fqdn, &self.domain, "", addr);
+                info!("{} from domain {} created with {}", name, self.domain, addr)
+            }
+        }
+
+        Ok(Updates {
+            updated: missing.len() as i32,
+            ..Updates::default()
+        })
+    }
+
     async fn ensure_current_ip(
         &self,
         record: &GoRecord,
@@ -100,14 +186,23 @@ impl GoClient<'_> {
             Ok(ip) => {
                 if ip != addr {
                     updated += 1;
-                    self.update_record(record, addr).await?;
-
-                    info!(
-                        "{} from domain {} updated from {} to {}",
-                        record.name, self.domain, record.data, addr
-                    )
+                    if self.dry_run {
+                        let fqdn = crate::core::fqdn(&record.name, &self.domain);
+                        self.changes.plan(&fqdn, &self.domain, &record.data, addr);
+                    } else {
+                        self.update_record(record, addr).await?;
+                        let fqdn = crate::core::fqdn(&record.name, &self.domain);
+                        self.changes.push(&fqdn, &self.domain, &record.data, addr);
+
+                        info!(
+                            "{} from domain {} updated from {} to {}",
+                            record.name, self.domain, record.data, addr
+                        )
+                    }
                 } else {
                     current += 1;
+                    self.changes
+                        .current(&crate::core::fqdn(&record.name, &self.domain));
                     debug!(
                         "{} from domain {} is already current",
                         record.name, self.domain
@@ -117,12 +212,19 @@ impl GoClient<'_> {
             Err(ref e) => {
                 updated += 1;
                 warn!("could not parse domain {} address {} as ipv4 -- will replace it. Original error: {}", record.name, record.data, e);
-                self.update_record(record, addr).await?;
+                if self.dry_run {
+                    let fqdn = crate::core::fqdn(&record.name, &self.domain);
+                    self.changes.plan(&fqdn, &self.domain, &record.data, addr);
+                } else {
+                    self.update_record(record, addr).await?;
+                    let fqdn = crate::core::fqdn(&record.name, &self.domain);
+                    self.changes.push(&fqdn, &self.domain, &record.data, addr);
 
-                info!(
-                    "{} from domain {} updated from {} to {}",
-                    record.name, self.domain, record.data, addr
-                )
+                    info!(
+                        "{} from domain {} updated from {} to {}",
+                        record.name, self.domain, record.data, addr
+                    )
+                }
             }
         }
 
@@ -134,6 +236,18 @@ impl GoClient<'_> {
     }
 }
 
+/// Confirms that GoDaddy accepts the credentials and returns the configured records that don't
+/// exist in the domain
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &GoDaddyConfig,
+    ip_type: IpType,
+) -> Result<Vec<String>, DnessError> {
+    let go_client = GoClient::create(ctx, config);
+    let records = go_client.fetch_records(ip_type).await?;
+    Ok(go_client.missing_domains(&records))
+}
+
 /// GoDaddy dynamic dns service works as the following:
 ///
 /// 1. Send a GET request to find all records in the domain
@@ -141,24 +255,20 @@ impl GoClient<'_> {
 /// 3. Update the remote IP as needed, ensuring that original properties are preserved in the
 ///    upload, so that we don't overwrite a property like TTL.
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &GoDaddyConfig,
     addr: IpAddr,
 ) -> Result<Updates, DnessError> {
-    let go_client = GoClient {
-        base_url: config.base_url.trim_end_matches('/').to_string(),
-        domain: config.domain.clone(),
-        key: config.key.clone(),
-        secret: config.secret.clone(),
-        records: config.records.iter().cloned().collect(),
-        client,
-    };
+    let go_client = GoClient::create(ctx, config);
 
     let records = go_client.fetch_records(IpType::from(addr)).await?;
-    let missing = go_client.log_missing_domains(&records) as i32;
-    let mut summary = Updates {
-        missing,
-        ..Updates::default()
+    let mut summary = if let Some(ttl) = go_client.create_ttl {
+        go_client.create_missing(&records, addr, ttl).await?
+    } else {
+        Updates {
+            missing: go_client.log_missing_domains(&records) as i32,
+            ..Updates::default()
+        }
     };
 
     for record in records {
@@ -173,7 +283,11 @@ pub async fn update_domains(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DnsTransport;
+    use crate::core::RecordStatus;
+    use crate::dns::DnsResolver;
     use serde_json::json;
+    use std::collections::HashMap;
 
     #[test]
     fn deserialize_go_records() {
@@ -229,12 +343,17 @@ mod tests {
                 ),
                 "/v1/domains/domain-2.com/records/A/@" => Response::text("Nice job!"),
                 "/v1/domains/domain-2.com/records/A/a" => Response::text("Nice job!"),
+                "/v1/domains/domain-2.com/records" => Response::text("Nice job!"),
                 "/v1/domains/domain-3.com/records/AAAA" => Response::from_data(
                     "application/json",
                     r#"[{"name": "@", "data": "2001:db8::2"}, {"name": "c", "data": "2001:db8::1"}]"#,
                 ),
                 "/v1/domains/domain-3.com/records/AAAA/@" => Response::text("Nice job!"),
                 "/v1/domains/domain-3.com/records/AAAA/c" => Response::text("Nice job!"),
+                "/v1/domains/domain-4.com/records/A" => Response::from_data(
+                    "application/json",
+                    r#"[{"name": "@", "data": "2.2.2.2"}, {"name": "a", "data": "2.1.2.2"}]"#,
+                ),
                 _ => Response::empty_404(),
             })
             .unwrap();
@@ -255,6 +374,10 @@ mod tests {
     async fn test_godaddy_unparseable_ipv4() {
         let (tx, addr) = godaddy_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(std::net::Ipv4Addr::new(2, 2, 2, 2));
         let config = GoDaddyConfig {
             base_url: format!("http://{}", addr),
@@ -263,9 +386,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -282,6 +411,14 @@ mod tests {
     async fn test_godaddy_grabbag() {
         let (tx, addr) = godaddy_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let changes = ChangeLog::default();
+        let ctx = Context {
+            changes: &changes,
+            ..Context::for_test(&http_client, &resolver)
+        };
         let new_ip = IpAddr::V4(std::net::Ipv4Addr::new(2, 2, 2, 2));
         let config = GoDaddyConfig {
             base_url: format!("http://{}", addr),
@@ -290,9 +427,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@"), String::from("a"), String::from("b")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -303,12 +446,26 @@ mod tests {
                 missing: 1,
             }
         );
+
+        let updated: Vec<String> = changes.take().into_iter().map(|x| x.record).collect();
+        assert_eq!(updated, vec![String::from("a.domain-2.com")]);
+        assert_eq!(
+            changes.take_unchanged(),
+            vec![
+                (String::from("b.domain-2.com"), RecordStatus::Missing),
+                (String::from("domain-2.com"), RecordStatus::Current),
+            ]
+        );
     }
 
     #[tokio::test]
     async fn test_godaddy_grabbag_ipv6() {
         let (tx, addr) = godaddy_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V6(std::net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2));
         let config = GoDaddyConfig {
             base_url: format!("http://{}", addr),
@@ -317,9 +474,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@"), String::from("c"), String::from("d")],
             ip_types: vec![IpType::V6],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -331,4 +494,82 @@ mod tests {
             }
         );
     }
+
+    #[tokio::test]
+    async fn test_godaddy_dry_run() {
+        // domain-4 doesn't accept updates, so the test fails if the update is sent
+        let (tx, addr) = godaddy_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context {
+            dry_run: true,
+            ..Context::for_test(&http_client, &resolver)
+        };
+        let new_ip = IpAddr::V4(std::net::Ipv4Addr::new(2, 2, 2, 2));
+        let config = GoDaddyConfig {
+            base_url: format!("http://{}", addr),
+            domain: String::from("domain-4.com"),
+            key: String::from("key-1"),
+            secret: String::from("secret-1"),
+            records: vec![String::from("@"), String::from("a")],
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 1,
+                missing: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_godaddy_create_missing() {
+        let (tx, addr) = godaddy_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(std::net::Ipv4Addr::new(2, 2, 2, 2));
+        let config = GoDaddyConfig {
+            base_url: format!("http://{}", addr),
+            domain: String::from("domain-2.com"),
+            key: String::from("key-1"),
+            secret: String::from("secret-1"),
+            records: vec![String::from("@"), String::from("a"), String::from("b")],
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: true,
+            create_ttl: 600,
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 1,
+                updated: 2,
+                missing: 0,
+            }
+        );
+    }
 }
@@ -1,13 +1,16 @@
-use crate::config::HeConfig;
-use crate::core::Updates;
-use crate::dns::DnsResolver;
+use crate::authoritative;
+use crate::config::{HeConfig, IpType, RetryConfig};
+use crate::core::{fqdn, Context, Updates};
 use crate::errors::DnessError;
+use crate::retry::SendRetry;
 use log::{info, warn};
 use std::net::IpAddr;
 
 #[derive(Debug)]
 pub struct HeProvider<'a> {
+    client: &'a reqwest::Client,
     config: &'a HeConfig,
+    retry: &'a RetryConfig,
 }
 
 impl HeProvider<'_> {
@@ -22,12 +25,13 @@ impl HeProvider<'_> {
         ];
 
         // annoyingly it looks like he closes the connection on every update
-        // so we have to allocate a new client for every request
-        let client = reqwest::Client::new();
-        let response = client
+        // so we ask for the connection to not be kept around for the next request
+        let response = self
+            .client
             .post(&url)
+            .header(reqwest::header::CONNECTION, "close")
             .form(&params)
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&url, "he update", e))?
             .error_for_status()
@@ -47,34 +51,55 @@ impl HeProvider<'_> {
     }
 }
 
+/// Returns the configured records that can't be resolved. The credentials can't be verified as the
+/// update endpoint is the only authenticated api.
+pub async fn missing_records(ctx: &Context<'_>, config: &HeConfig, ip_type: IpType) -> Vec<String> {
+    let mut missing = Vec::new();
+    for record in &config.records {
+        let dns_query = format!("{}.", fqdn(record, &config.hostname));
+        if ctx.resolver.ip_lookup(&dns_query, ip_type).await.is_err() {
+            missing.push(record.clone());
+        }
+    }
+    missing
+}
+
 pub async fn update_domains(
-    _client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &HeConfig,
     wan: IpAddr,
 ) -> Result<Updates, DnessError> {
     // uses the same strategy as namecheap where we get the current records
     // via dns and check if they need to be updated
-    let resolver = DnsResolver::create_cloudflare().await?;
-    let he = HeProvider { config };
+    let he = HeProvider {
+        client: ctx.http,
+        config,
+        retry: ctx.retry,
+    };
 
     let mut results = Updates::default();
+    let mut updated = Vec::new();
 
     for record in &config.records {
-        let host_record = if record == "@" {
-            config.hostname.clone()
-        } else {
-            format!("{}.{}", record, &config.hostname)
-        };
+        let host_record = fqdn(record, &config.hostname);
 
         let dns_query = format!("{}.", &host_record);
-        let response = resolver.ip_lookup(&dns_query, wan.into()).await;
+        let response = authoritative::current_ip(ctx, &dns_query, wan).await;
 
         match response {
             Ok(ip) => {
                 if ip == wan {
                     results.current += 1;
+                    ctx.changes.current(&host_record);
+                } else if ctx.dry_run {
+                    ctx.changes
+                        .plan(&host_record, &config.hostname, &ip.to_string(), wan);
+                    results.updated += 1;
                 } else {
                     he.update_domain(&host_record, wan).await?;
+                    updated.push(host_record.clone());
+                    ctx.changes
+                        .push(&host_record, &config.hostname, &ip.to_string(), wan);
                     info!(
                         "{} from domain {} updated from {} to {}",
                         record, config.hostname, ip, wan
@@ -82,24 +107,27 @@ pub async fn update_domains(
                     results.updated += 1;
                 }
             }
+            Err(e) if !e.is_not_found() => return Err(e.into()),
             Err(e) => {
-                // Could be a network issue or it could be that the record didn't exist.
                 warn!(
                     "resolving he record ({}) encountered an error: {}",
                     record, e
                 );
                 results.missing += 1;
+                ctx.changes.missing(&host_record);
             }
         }
     }
 
+    authoritative::verify(ctx, &updated, wan).await?;
     Ok(results)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::IpType;
+    use crate::dns::DnsResolver;
+    use std::collections::HashMap;
     use std::net::Ipv4Addr;
 
     macro_rules! he_server {
@@ -129,6 +157,9 @@ mod tests {
     async fn test_he_update() {
         let (tx, addr) = he_server!();
         let http_client = reqwest::Client::new();
+        let old_ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let resolver = DnsResolver::for_test(old_ip).await;
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = HeConfig {
             base_url: format!("http://{}", addr),
@@ -136,9 +167,13 @@ mod tests {
             password: String::from("secret-1"),
             records: vec![String::from("@")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -0,0 +1,200 @@
+use crate::config::{DnsConfig, DomainConfig, IpType};
+use crate::core::RecordChange;
+use log::{debug, warn};
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+/// Commands are run through the shell so that they can use pipes, redirection, and the like
+fn shell(command: &str) -> Command {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let mut shell = Command::new(program);
+    shell.arg(flag).arg(command);
+
+    // In its own process group, so that whatever the hook starts can be killed along with it
+    #[cfg(unix)]
+    shell.process_group(0);
+    shell
+}
+
+/// Kills the hook and everything it started
+async fn kill(child: &mut Child) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: kill has no memory safety requirements. The group is the hook's own, as it was
+        // spawned with a process group id of zero.
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    child.kill().await
+}
+
+/// Runs the hook, killing it once it runs past the timeout so that it can't hold up the update
+async fn run(command: &str, env: &[(&str, String)], timeout: Duration) {
+    let mut shell = shell(command);
+    shell
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .kill_on_drop(true);
+
+    let mut child = match shell.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("unable to run hook `{}`: {}", command, e);
+            return;
+        }
+    };
+
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => debug!("hook `{}` succeeded", command),
+        Ok(Ok(status)) => warn!("hook `{}` failed: {}", command, status),
+        Ok(Err(e)) => warn!("unable to run hook `{}`: {}", command, e),
+        Err(_) => {
+            if let Err(e) = kill(&mut child).await {
+                debug!("unable to kill hook `{}`: {}", command, e);
+            }
+            warn!(
+                "hook `{}` failed: timed out after {:.1}s",
+                command,
+                timeout.as_secs_f64()
+            );
+        }
+    }
+}
+
+/// Runs the domain's `on_update` hook (or the global one) once for each changed record
+pub async fn on_update(config: &DnsConfig, domain: &DomainConfig, changes: &[RecordChange]) {
+    let command = match domain.hooks().0.or(config.on_update.as_deref()) {
+        Some(command) => command,
+        None => return,
+    };
+
+    for change in changes {
+        let env = [
+            ("DNESS_RECORD", change.record.clone()),
+            (
+                "DNESS_RECORD_TYPE",
+                String::from(IpType::from(change.new).record_type()),
+            ),
+            ("DNESS_OLD_IP", change.old.clone()),
+            ("DNESS_NEW_IP", change.new.to_string()),
+            ("DNESS_PROVIDER", String::from(domain.provider())),
+            ("DNESS_ZONE", change.zone.clone()),
+        ];
+        run(command, &env, Duration::from_secs(config.hook_timeout)).await;
+    }
+}
+
+/// Runs the domain's `on_failure` hook (or the global one) with a description of the error. The
+/// domain is absent when the failure isn't specific to one, like when the WAN IP can't be resolved.
+pub async fn on_failure(config: &DnsConfig, domain: Option<&DomainConfig>, error: &str) {
+    let command = match domain
+        .and_then(|d| d.hooks().1)
+        .or(config.on_failure.as_deref())
+    {
+        Some(command) => command,
+        None => return,
+    };
+
+    let mut env = vec![("DNESS_ERROR", String::from(error))];
+    if let Some(domain) = domain {
+        env.push(("DNESS_PROVIDER", String::from(domain.provider())));
+        env.push(("DNESS_ZONE", String::from(domain.zone())));
+    }
+    run(command, &env, Duration::from_secs(config.hook_timeout)).await;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::HeConfig;
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn he_domain(on_update: Option<String>) -> DomainConfig {
+        DomainConfig::He(HeConfig {
+            base_url: String::from("http://localhost"),
+            hostname: String::from("example.com"),
+            password: String::from("secret"),
+            records: vec![String::from("@")],
+            ip_types: vec![IpType::V4],
+            on_update,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+        })
+    }
+
+    #[tokio::test]
+    async fn update_hook_environment() {
+        let path = std::env::temp_dir().join(format!("dness-hook-{}.txt", std::process::id()));
+        let command = format!(
+            "echo \"$DNESS_RECORD $DNESS_RECORD_TYPE $DNESS_OLD_IP $DNESS_NEW_IP $DNESS_PROVIDER $DNESS_ZONE\" >> {}",
+            path.display()
+        );
+
+        let changes = vec![
+            RecordChange {
+                record: String::from("example.com"),
+                zone: String::from("example.com"),
+                old: String::from("2.2.2.1"),
+                new: IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)),
+            },
+            RecordChange {
+                record: String::from("a.example.com"),
+                zone: String::from("example.com"),
+                old: String::new(),
+                new: IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)),
+            },
+        ];
+
+        // The domain's hook takes precedence over the global one
+        let config = DnsConfig {
+            on_update: Some(String::from("exit 1")),
+            ..DnsConfig::default()
+        };
+        on_update(&config, &he_domain(Some(command)), &changes).await;
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            output,
+            "example.com A 2.2.2.1 2.2.2.2 he example.com\na.example.com A  2.2.2.2 he example.com\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn failure_hook_falls_back_to_global() {
+        let path = std::env::temp_dir().join(format!("dness-hook-fail-{}.txt", std::process::id()));
+        let config = DnsConfig {
+            on_failure: Some(format!(
+                "echo \"$DNESS_PROVIDER: $DNESS_ERROR\" > {}",
+                path.display()
+            )),
+            ..DnsConfig::default()
+        };
+        on_failure(&config, Some(&he_domain(None)), "bad password").await;
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(output, "he: bad password\n");
+    }
+
+    #[tokio::test]
+    async fn hook_killed_after_timeout() {
+        let path = std::env::temp_dir().join(format!("dness-hook-slow-{}.txt", std::process::id()));
+        let command = format!("(sleep 1; echo late > {}) & wait", path.display());
+
+        let start = std::time::Instant::now();
+        run(&command, &[], Duration::from_millis(100)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // The shell's whole process group was killed, so the background job never wrote the file
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!path.exists());
+    }
+}
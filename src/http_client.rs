@@ -0,0 +1,160 @@
+use crate::config::HttpConfig;
+use crate::errors::DnessError;
+use reqwest::{Certificate, ClientBuilder, Proxy};
+use std::time::Duration;
+
+/// Creates the client that every provider and http based WAN IP resolver sends requests with
+pub fn create(config: &HttpConfig) -> Result<reqwest::Client, DnessError> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .timeout(Duration::from_secs(config.timeout))
+        .user_agent(&config.user_agent)
+        .local_address(config.local_address);
+
+    if let Some(url) = &config.proxy {
+        let proxy = Proxy::all(url)
+            .map_err(|e| DnessError::message(format!("invalid http proxy {}: {}", url, e)))?;
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(interface) = &config.interface {
+        builder = bind_interface(builder, interface)?;
+    }
+
+    for path in &config.ca_certs {
+        let pem = std::fs::read(path).map_err(|e| {
+            DnessError::message(format!(
+                "could not read ca certificates {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let certs = Certificate::from_pem_bundle(&pem)
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .ok_or_else(|| {
+                DnessError::message(format!(
+                    "no pem encoded certificates found in {}",
+                    path.display()
+                ))
+            })?;
+
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| DnessError::message(format!("could not create http client: {}", e)))
+}
+
+/// Creates the client for requests to devices on the local network, like the router's UPnP
+/// service. These must not go through a proxy, nor be bound to the address or interface that
+/// provider requests are sent from.
+pub fn create_local(config: &HttpConfig) -> Result<reqwest::Client, DnessError> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .timeout(Duration::from_secs(config.timeout))
+        .user_agent(&config.user_agent)
+        .no_proxy()
+        .build()
+        .map_err(|e| DnessError::message(format!("could not create http client: {}", e)))
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_interface(builder: ClientBuilder, interface: &str) -> Result<ClientBuilder, DnessError> {
+    Ok(builder.interface(interface))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_interface(_builder: ClientBuilder, interface: &str) -> Result<ClientBuilder, DnessError> {
+    Err(DnessError::message(format!(
+        "can't bind http requests to {}, as binding to an interface is only supported on linux",
+        interface
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn client_sends_user_agent() {
+        let server = rouille::Server::new("localhost:0", |request| {
+            rouille::Response::text(request.header("User-Agent").unwrap_or_default())
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, sender) = server.stoppable();
+
+        let config = HttpConfig {
+            user_agent: String::from("dness-test/1.0 admin@example.com"),
+            local_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ..HttpConfig::default()
+        };
+        let client = create(&config).unwrap();
+        let body = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "dness-test/1.0 admin@example.com");
+
+        sender.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_client_skips_proxy() {
+        let server =
+            rouille::Server::new("localhost:0", |_request| rouille::Response::text("direct"))
+                .unwrap();
+        let addr = server.server_addr();
+        let (handle, sender) = server.stoppable();
+
+        // Nothing listens on the discard port, so a proxied request would fail
+        let config = HttpConfig {
+            proxy: Some(String::from("http://127.0.0.1:9")),
+            ..HttpConfig::default()
+        };
+        let url = format!("http://{}/", addr);
+        assert!(create(&config).unwrap().get(&url).send().await.is_err());
+
+        let body = create_local(&config)
+            .unwrap()
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "direct");
+
+        sender.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn reject_bad_settings() {
+        let config = HttpConfig {
+            proxy: Some(String::from("not a url")),
+            ..HttpConfig::default()
+        };
+        assert!(create(&config).is_err());
+
+        let config = HttpConfig {
+            ca_certs: vec![PathBuf::from("assets/base-config.toml")],
+            ..HttpConfig::default()
+        };
+        let err = create(&config).unwrap_err();
+        assert!(err.to_string().starts_with("no pem encoded certificates"));
+    }
+}
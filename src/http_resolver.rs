@@ -0,0 +1,229 @@
+use crate::config::{HttpResolverConfig, IpType, RetryConfig};
+use crate::errors::DnessError;
+use crate::retry::SendRetry;
+use regex::Regex;
+use std::net::IpAddr;
+
+/// Resolves the WAN IP by asking a "what is my ip" web service
+#[derive(Debug)]
+pub struct HttpResolver {
+    config: HttpResolverConfig,
+    regex: Option<Regex>,
+    retry: RetryConfig,
+}
+
+impl HttpResolver {
+    pub fn create(config: HttpResolverConfig) -> Result<Self, DnessError> {
+        if config.regex.is_some() && config.json_pointer.is_some() {
+            return Err(DnessError::message(String::from(
+                "http ip resolver: only one of regex or json_pointer can be given",
+            )));
+        }
+
+        let regex = match &config.regex {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| {
+                DnessError::message(format!("http ip resolver: invalid regex: {}", e))
+            })?),
+            None => None,
+        };
+
+        Ok(HttpResolver {
+            config,
+            regex,
+            retry: RetryConfig::default(),
+        })
+    }
+
+    /// The ipify service, which responds with a bare address
+    pub fn ipify() -> Self {
+        HttpResolver {
+            config: HttpResolverConfig {
+                v4_url: Some(String::from("https://api.ipify.org/")),
+                v6_url: Some(String::from("https://api6.ipify.org/")),
+                ..HttpResolverConfig::default()
+            },
+            regex: None,
+            retry: RetryConfig::default(),
+        }
+    }
+
+    pub fn with_retry(self, retry: &RetryConfig) -> Self {
+        HttpResolver {
+            retry: retry.clone(),
+            ..self
+        }
+    }
+
+    /// Pulls the address out of the response body, either by the first capture group of the regex
+    /// (or the whole match when there are no groups), by json pointer, or as the entire body
+    fn extract(&self, body: &str) -> Result<String, DnessError> {
+        if let Some(regex) = &self.regex {
+            let captures = regex.captures(body).ok_or_else(|| {
+                DnessError::message(format!("regex did not match response: {}", body))
+            })?;
+            let found = captures.get(1).or_else(|| captures.get(0));
+            Ok(found
+                .map(|x| x.as_str())
+                .unwrap_or_default()
+                .trim()
+                .to_string())
+        } else if let Some(pointer) = &self.config.json_pointer {
+            let value: serde_json::Value = serde_json::from_str(body).map_err(|e| {
+                DnessError::message(format!("unable to parse response as json: {}", e))
+            })?;
+            let found = value.pointer(pointer).and_then(|x| x.as_str());
+            let found = found.ok_or_else(|| {
+                DnessError::message(format!("json pointer {} did not find a string", pointer))
+            })?;
+            Ok(found.trim().to_string())
+        } else {
+            Ok(body.trim().to_string())
+        }
+    }
+
+    pub async fn resolve(
+        &self,
+        client: &reqwest::Client,
+        ip_type: IpType,
+    ) -> Result<IpAddr, DnessError> {
+        let url = match ip_type {
+            IpType::V4 => self.config.v4_url.as_ref(),
+            IpType::V6 => self.config.v6_url.as_ref(),
+        };
+
+        let url = url.ok_or_else(|| {
+            DnessError::message(format!(
+                "http ip resolver has no url for {} records",
+                ip_type.record_type()
+            ))
+        })?;
+
+        let mut request = client.get(url);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let body = request
+            .send_retry(&self.retry)
+            .await
+            .map_err(|e| DnessError::send_http(url, "http get ip", e))?
+            .error_for_status()
+            .map_err(|e| DnessError::bad_response(url, "http get ip", e))?
+            .text()
+            .await
+            .map_err(|e| DnessError::deserialize(url, "http get ip", e))?;
+
+        let ip_text = self.extract(&body)?;
+        let ip = ip_text
+            .parse::<IpAddr>()
+            .map_err(|_| DnessError::message(format!("unable to parse {} as an ip", ip_text)))?;
+
+        if IpType::from(ip) != ip_type {
+            return Err(DnessError::message(format!(
+                "expected an {} address from {} but received {}",
+                ip_type.record_type(),
+                url,
+                ip
+            )));
+        }
+
+        Ok(ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+
+    fn resolver(regex: Option<&str>, json_pointer: Option<&str>) -> HttpResolver {
+        HttpResolver::create(HttpResolverConfig {
+            regex: regex.map(String::from),
+            json_pointer: json_pointer.map(String::from),
+            ..HttpResolverConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn extract_bare() {
+        let resolver = resolver(None, None);
+        assert_eq!(resolver.extract("2.2.2.2\n").unwrap(), "2.2.2.2");
+    }
+
+    #[test]
+    fn extract_regex() {
+        let body = "fl=1f1\nh=1.1.1.1\nip=2.2.2.2\nts=1700000000.1\n";
+        let resolver = resolver(Some("(?m)^ip=(.+)$"), None);
+        assert_eq!(resolver.extract(body).unwrap(), "2.2.2.2");
+
+        let resolver = self::resolver(Some(r"\d+\.\d+\.\d+\.\d+"), None);
+        assert_eq!(resolver.extract("Your ip: 2.2.2.2").unwrap(), "2.2.2.2");
+        assert!(resolver.extract("no address").is_err());
+    }
+
+    #[test]
+    fn extract_json_pointer() {
+        let body = r#"{"ip": "2.2.2.2", "country": "Antarctica", "asn": {"id": "AS1"}}"#;
+        let resolver = resolver(None, Some("/ip"));
+        assert_eq!(resolver.extract(body).unwrap(), "2.2.2.2");
+
+        let resolver = self::resolver(None, Some("/missing"));
+        assert!(resolver.extract(body).is_err());
+    }
+
+    #[test]
+    fn create_rejects_both_extractors() {
+        let config = HttpResolverConfig {
+            regex: Some(String::from("ip=(.+)")),
+            json_pointer: Some(String::from("/ip")),
+            ..HttpResolverConfig::default()
+        };
+        assert!(HttpResolver::create(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_http() {
+        use rouille::Response;
+        use rouille::Server;
+
+        let server = Server::new("localhost:0", |request| {
+            match (request.url().as_str(), request.header("X-Api-Key")) {
+                ("/ip", Some("secret")) => {
+                    Response::from_data("application/json", r#"{"ip": "2.2.2.2"}"#)
+                }
+                _ => Response::empty_404(),
+            }
+        })
+        .unwrap();
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let addr = server.server_addr();
+        std::thread::spawn(move || {
+            while rx.try_recv().is_err() {
+                server.poll();
+                std::thread::sleep(std::time::Duration::from_millis(50))
+            }
+        });
+
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("X-Api-Key"), String::from("secret"));
+        let resolver = HttpResolver::create(HttpResolverConfig {
+            v4_url: Some(format!("http://{}/ip", addr)),
+            v6_url: None,
+            headers,
+            regex: None,
+            json_pointer: Some(String::from("/ip")),
+        })
+        .unwrap();
+
+        let client = reqwest::Client::new();
+        let ip = resolver.resolve(&client, IpType::V4).await.unwrap();
+        let v6 = resolver.resolve(&client, IpType::V6).await;
+        tx.send(()).unwrap();
+
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)));
+        assert!(v6.is_err());
+    }
+}
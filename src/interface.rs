@@ -0,0 +1,234 @@
+use crate::config::{InterfaceResolverConfig, IpType, Ipv6Preference};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::net::{IpAddr, Ipv6Addr};
+
+// Address flags from linux/if_addr.h that disqualify an address from being published
+const IFA_F_TEMPORARY: u8 = 0x01;
+const IFA_F_DADFAILED: u8 = 0x08;
+const IFA_F_DEPRECATED: u8 = 0x20;
+const IFA_F_TENTATIVE: u8 = 0x40;
+const UNUSABLE_FLAGS: u8 = IFA_F_TEMPORARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+
+#[derive(Debug)]
+pub struct InterfaceError {
+    kind: InterfaceErrorKind,
+}
+
+#[derive(Debug)]
+pub enum InterfaceErrorKind {
+    Enumerate(IoError),
+    NoAddress { interface: String, ip_type: IpType },
+}
+
+impl error::Error for InterfaceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            InterfaceErrorKind::Enumerate(ref e) => Some(e),
+            InterfaceErrorKind::NoAddress { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interface resolver: ")?;
+        match &self.kind {
+            InterfaceErrorKind::Enumerate(_) => write!(f, "unable to list network interfaces"),
+            InterfaceErrorKind::NoAddress { interface, ip_type } => write!(
+                f,
+                "no usable {} address found on {}",
+                ip_type.record_type(),
+                interface
+            ),
+        }
+    }
+}
+
+/// Parses /proc/net/if_inet6, where each line is an address (as 32 hex digits), the interface
+/// index, the prefix length, the scope, the flags, and the interface name
+fn parse_if_inet6(contents: &str) -> HashMap<Ipv6Addr, u8> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let addr = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let flags = u8::from_str_radix(fields.get(4)?, 16).ok()?;
+            Some((Ipv6Addr::from(addr), flags))
+        })
+        .collect()
+}
+
+/// The flags of the host's IPv6 addresses. Only linux exposes whether an address is temporary or
+/// deprecated, elsewhere every address is assumed to be usable.
+fn ipv6_flags() -> HashMap<Ipv6Addr, u8> {
+    if cfg!(target_os = "linux") {
+        std::fs::read_to_string("/proc/net/if_inet6")
+            .map(|x| parse_if_inet6(&x))
+            .unwrap_or_default()
+    } else {
+        HashMap::new()
+    }
+}
+
+/// Whether the interface identifier was derived from a MAC address (modified EUI-64), which is
+/// recognizable by the ff:fe in its middle
+fn is_eui64(addr: &Ipv6Addr) -> bool {
+    let octets = addr.octets();
+    octets[11] == 0xff && octets[12] == 0xfe
+}
+
+/// Whether the address is globally reachable rather than link-local or unique local (fc00::/7)
+fn is_global_ipv6(addr: &Ipv6Addr) -> bool {
+    let first = addr.segments()[0];
+    !addr.is_loopback()
+        && !addr.is_unspecified()
+        && !addr.is_multicast()
+        && (first & 0xffc0) != 0xfe80
+        && (first & 0xfe00) != 0xfc00
+}
+
+/// Picks the address to publish among an interface's addresses, preserving the interface's order
+/// unless a preference is given
+fn select_addr(
+    addrs: &[IpAddr],
+    flags: &HashMap<Ipv6Addr, u8>,
+    ip_type: IpType,
+    prefer: Option<Ipv6Preference>,
+) -> Option<IpAddr> {
+    match ip_type {
+        IpType::V4 => addrs.iter().copied().find(|x| match x {
+            IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_link_local() && !ip.is_unspecified(),
+            IpAddr::V6(_) => false,
+        }),
+        IpType::V6 => {
+            let candidates: Vec<Ipv6Addr> = addrs
+                .iter()
+                .filter_map(|x| match x {
+                    IpAddr::V6(ip) => Some(*ip),
+                    IpAddr::V4(_) => None,
+                })
+                .filter(is_global_ipv6)
+                .filter(|x| flags.get(x).is_none_or(|f| f & UNUSABLE_FLAGS == 0))
+                .collect();
+
+            let preferred = match prefer {
+                Some(Ipv6Preference::Eui64) => candidates.iter().find(|x| is_eui64(x)),
+                Some(Ipv6Preference::StablePrivacy) => candidates.iter().find(|x| !is_eui64(x)),
+                None => None,
+            };
+
+            preferred
+                .or_else(|| candidates.first())
+                .map(|x| IpAddr::V6(*x))
+        }
+    }
+}
+
+/// Reads the address to publish from a local network interface
+pub fn interface_addr(
+    config: &InterfaceResolverConfig,
+    ip_type: IpType,
+) -> Result<IpAddr, InterfaceError> {
+    let addrs: Vec<IpAddr> = if_addrs::get_if_addrs()
+        .map_err(|e| InterfaceError {
+            kind: InterfaceErrorKind::Enumerate(e),
+        })?
+        .into_iter()
+        .filter(|x| x.name == config.name)
+        .map(|x| x.ip())
+        .collect();
+
+    let flags = match ip_type {
+        IpType::V4 => HashMap::new(),
+        IpType::V6 => ipv6_flags(),
+    };
+
+    select_addr(&addrs, &flags, ip_type, config.prefer).ok_or_else(|| InterfaceError {
+        kind: InterfaceErrorKind::NoAddress {
+            interface: config.name.clone(),
+            ip_type,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IF_INET6: &str = "\
+fe80000000000000021122fffe334455 02 40 20 80     eth0
+20010db800000000021122fffe334455 02 40 00 00     eth0
+20010db800000000a1b2c3d4e5f60718 02 40 00 01     eth0
+20010db800000000123456789abcdef0 02 40 00 00     eth0
+20010db80000000000000000000000aa 02 40 00 20     eth0
+";
+
+    fn addrs() -> Vec<IpAddr> {
+        vec![
+            IpAddr::V4(Ipv4Addr::new(169, 254, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)),
+            "fe80::211:22ff:fe33:4455".parse().unwrap(),
+            "fd00::1".parse().unwrap(),
+            "2001:db8::aa".parse().unwrap(),
+            "2001:db8::a1b2:c3d4:e5f6:718".parse().unwrap(),
+            "2001:db8::211:22ff:fe33:4455".parse().unwrap(),
+            "2001:db8::1234:5678:9abc:def0".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn parse_if_inet6_flags() {
+        let flags = parse_if_inet6(IF_INET6);
+        assert_eq!(flags.len(), 5);
+        assert_eq!(
+            flags.get(&"2001:db8::a1b2:c3d4:e5f6:718".parse().unwrap()),
+            Some(&IFA_F_TEMPORARY)
+        );
+        assert_eq!(
+            flags.get(&"2001:db8::aa".parse().unwrap()),
+            Some(&IFA_F_DEPRECATED)
+        );
+    }
+
+    #[test]
+    fn select_ipv4() {
+        let addr = select_addr(&addrs(), &HashMap::new(), IpType::V4, None);
+        assert_eq!(addr, Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5))));
+    }
+
+    #[test]
+    fn select_ipv6_skips_temporary_and_deprecated() {
+        let flags = parse_if_inet6(IF_INET6);
+        let addr = select_addr(&addrs(), &flags, IpType::V6, None);
+        assert_eq!(addr, Some("2001:db8::211:22ff:fe33:4455".parse().unwrap()));
+    }
+
+    #[test]
+    fn select_ipv6_preference() {
+        let flags = parse_if_inet6(IF_INET6);
+        let eui64 = select_addr(&addrs(), &flags, IpType::V6, Some(Ipv6Preference::Eui64));
+        assert_eq!(eui64, Some("2001:db8::211:22ff:fe33:4455".parse().unwrap()));
+
+        let stable = select_addr(
+            &addrs(),
+            &flags,
+            IpType::V6,
+            Some(Ipv6Preference::StablePrivacy),
+        );
+        assert_eq!(
+            stable,
+            Some("2001:db8::1234:5678:9abc:def0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn select_missing() {
+        let addrs = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
+        assert_eq!(select_addr(&addrs, &HashMap::new(), IpType::V4, None), None);
+        assert_eq!(select_addr(&addrs, &HashMap::new(), IpType::V6, None), None);
+    }
+}
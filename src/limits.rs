@@ -0,0 +1,75 @@
+use crate::config::DnsConfig;
+use std::collections::HashMap;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// How many domain updates may run at once, overall and per provider
+pub struct Limits {
+    global: Semaphore,
+    providers: HashMap<String, Semaphore>,
+}
+
+/// Held for the duration of an update
+pub struct Permit<'a> {
+    _provider: Option<SemaphorePermit<'a>>,
+    _global: SemaphorePermit<'a>,
+}
+
+impl Limits {
+    pub fn new(config: &DnsConfig) -> Self {
+        Limits {
+            global: Semaphore::new(config.max_concurrency.max(1)),
+            providers: config
+                .provider_concurrency
+                .iter()
+                .map(|(provider, limit)| (provider.clone(), Semaphore::new((*limit).max(1))))
+                .collect(),
+        }
+    }
+
+    /// Waits for the provider's turn before taking one of the overall slots, so that updates
+    /// queued behind a capped provider don't hold up the domains of other providers
+    pub async fn acquire(&self, provider: &str) -> Permit<'_> {
+        // The semaphores are never closed, so acquiring can't fail
+        let provider = match self.providers.get(provider) {
+            Some(limit) => limit.acquire().await.ok(),
+            None => None,
+        };
+        let global = self.global.acquire().await.unwrap();
+        Permit {
+            _provider: provider,
+            _global: global,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn capped_provider_leaves_slots_for_others() {
+        let mut config = DnsConfig {
+            max_concurrency: 2,
+            ..DnsConfig::default()
+        };
+        config
+            .provider_concurrency
+            .insert(String::from("godaddy"), 1);
+        let limits = Limits::new(&config);
+
+        let first = limits.acquire("godaddy").await;
+
+        // The second godaddy update waits for the first without taking an overall slot
+        let timeout = Duration::from_millis(100);
+        let mut waiting = Box::pin(limits.acquire("godaddy"));
+        assert!(tokio::time::timeout(timeout, &mut waiting).await.is_err());
+
+        let other = tokio::time::timeout(timeout, limits.acquire("cloudflare")).await;
+        assert!(other.is_ok());
+
+        drop(other);
+        drop(first);
+        assert!(tokio::time::timeout(timeout, waiting).await.is_ok());
+    }
+}
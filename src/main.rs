@@ -1,14 +1,31 @@
+mod authoritative;
+mod check;
 mod cloudflare;
 mod config;
 mod core;
 mod dns;
 mod dynu;
 mod errors;
+mod gateway;
 mod godaddy;
 mod he;
+mod hooks;
+mod http_client;
+mod http_resolver;
+mod interface;
+mod limits;
+mod metrics;
 mod namecheap;
 mod noip;
+mod notify;
 mod porkbun;
+mod prefix;
+mod report;
+mod resolvers;
+mod retry;
+mod rfc2136;
+mod state;
+mod stun;
 
 // Avoid musl's default allocator due to lackluster performance
 // https://nickb.dev/blog/default-musl-allocator-considered-harmful-to-performance
@@ -16,52 +33,108 @@ mod porkbun;
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+use crate::authoritative::NameServers;
 use crate::config::{parse_config, DnsConfig, DomainConfig, IpType};
-use crate::core::Updates;
-use crate::dns::wan_lookup_ip;
-use crate::errors::DnessError;
+use crate::core::{ChangeLog, Context, RecordChange, RecordStatus, Updates};
+use crate::dns::DnsResolver;
+use crate::errors::error_chain;
+use crate::limits::Limits;
+use crate::metrics::Metrics;
+use crate::notify::Notification;
+use crate::report::{AddressReport, DomainReport, RecordReport, Report};
+use crate::resolvers::IpResolvers;
+use crate::state::State;
 use chrono::Duration;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use log::{error, info, LevelFilter};
 use std::error;
 use std::fmt::Write;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio::time::MissedTickBehavior;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Opt {
     /// Sets a custom config file
-    #[structopt(short, long)]
+    #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// Keep running and update the records every `interval` seconds (from the config)
+    #[arg(short, long)]
+    daemon: bool,
+
+    /// Ignore the state file and check every record with its provider
+    #[arg(short, long)]
+    force: bool,
+
+    /// Print the records that would change without updating them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With "json", print a report of each run to stdout and move the log to stderr
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn log_err(context: &str, err: Box<dyn error::Error>) {
-    let mut msg = String::new();
-    let _ = writeln!(msg, "{} ", context);
-    let _ = write!(msg, "\tcaused by: {}", err);
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the configuration without updating any records
+    Check {
+        /// Also contact each provider to verify credentials and that the records exist
+        #[arg(long)]
+        online: bool,
+    },
+}
 
-    let mut ie = err.source();
-    while let Some(cause) = ie {
+fn log_err(context: &str, err: Box<dyn error::Error>) {
+    let chain = error_chain(context, err.as_ref());
+    let mut msg = format!("{} ", chain[0]);
+    for cause in &chain[1..] {
         let _ = write!(msg, "\n\tcaused by: {}", cause);
-        ie = cause.source();
     }
 
     error!("{}", msg);
 }
 
-fn init_logging(lvl: LevelFilter) {
+fn init_logging(lvl: LevelFilter, output: Output) {
+    // Keep stdout clean for the json report
+    let target = match output {
+        Output::Text => env_logger::Target::Stdout,
+        Output::Json => env_logger::Target::Stderr,
+    };
+
     env_logger::Builder::from_default_env()
         .filter_level(lvl)
-        .target(env_logger::Target::Stdout)
+        .target(target)
         .init();
 }
 
+fn print_report(output: Output, report: &Report) {
+    if output == Output::Json {
+        match serde_json::to_string(report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => log_err("could not serialize the report", Box::new(e)),
+        }
+    }
+}
+
 /// Parses the TOML configuration. If no configuration file is present, the default configuration
 /// is returned so that the WAN IP can still be logged on execution. If there is an error parsing
 /// the configuration file, exit with a non-zero status code.
-fn init_configuration<T: AsRef<Path>>(file: Option<T>) -> DnsConfig {
+fn init_configuration<T: AsRef<Path>>(file: Option<T>, output: Output) -> DnsConfig {
     if let Some(config_file) = file {
         let path = config_file.as_ref();
         match parse_config(path) {
@@ -69,7 +142,7 @@ fn init_configuration<T: AsRef<Path>>(file: Option<T>) -> DnsConfig {
             Err(e) => {
                 // If there is an error during configuration, we assume a log level of Warn so that
                 // the user will see the error printed.
-                init_logging(LevelFilter::Warn);
+                init_logging(LevelFilter::Warn, output);
                 let desc = format!("could not configure application from: {}", path.display());
                 log_err(&desc, Box::new(e));
                 std::process::exit(1)
@@ -80,44 +153,6 @@ fn init_configuration<T: AsRef<Path>>(file: Option<T>) -> DnsConfig {
     }
 }
 
-async fn ipify_resolve_ip(client: &reqwest::Client, ip_type: IpType) -> Result<IpAddr, DnessError> {
-    let ipify_url = match ip_type {
-        IpType::V4 => "https://api.ipify.org/",
-        IpType::V6 => "https://api6.ipify.org/",
-    };
-    let ip_text = client
-        .get(ipify_url)
-        .send()
-        .await
-        .map_err(|e| DnessError::send_http(ipify_url, "ipify get ip", e))?
-        .error_for_status()
-        .map_err(|e| DnessError::bad_response(ipify_url, "ipify get ip", e))?
-        .text()
-        .await
-        .map_err(|e| DnessError::deserialize(ipify_url, "ipify get ip", e))?;
-
-    let ip = ip_text
-        .parse::<IpAddr>()
-        .map_err(|_| DnessError::message(format!("unable to parse {} as an ip", &ip_text)))?;
-    Ok(ip)
-}
-
-/// Resolves the WAN IP or exits with a non-zero status code
-async fn resolve_ip(
-    client: &reqwest::Client,
-    config: &DnsConfig,
-    ip_type: IpType,
-) -> Result<IpAddr, DnessError> {
-    match config.ip_resolver.to_ascii_lowercase().as_str() {
-        "opendns" => wan_lookup_ip(ip_type).await.map_err(|x| x.into()),
-        "ipify" => ipify_resolve_ip(client, ip_type).await,
-        _ => {
-            error!("unrecognized ip resolver: {}", config.ip_resolver);
-            std::process::exit(1)
-        }
-    }
-}
-
 fn elapsed(start: Instant) -> String {
     Duration::from_std(Instant::now().duration_since(start))
         .map(|x| format!("{}ms", x.num_milliseconds()))
@@ -125,123 +160,518 @@ fn elapsed(start: Instant) -> String {
 }
 
 async fn update_provider(
-    http_client: &reqwest::Client,
+    ctx: &Context<'_>,
     addr: IpAddr,
     domain: &DomainConfig,
 ) -> Result<Updates, Box<dyn std::error::Error>> {
     match domain {
         DomainConfig::Cloudflare(domain_config) => {
-            cloudflare::update_domains(http_client, domain_config, addr)
-                .await
-                .map_err(|e| e.into())
-        }
-        DomainConfig::GoDaddy(domain_config) => {
-            godaddy::update_domains(http_client, domain_config, addr)
+            cloudflare::update_domains(ctx, domain_config, addr)
                 .await
                 .map_err(|e| e.into())
         }
+        DomainConfig::GoDaddy(domain_config) => godaddy::update_domains(ctx, domain_config, addr)
+            .await
+            .map_err(|e| e.into()),
         DomainConfig::Namecheap(domain_config) => {
-            namecheap::update_domains(http_client, domain_config, addr)
+            namecheap::update_domains(ctx, domain_config, addr)
                 .await
                 .map_err(|e| e.into())
         }
-        DomainConfig::He(domain_config) => he::update_domains(http_client, domain_config, addr)
+        DomainConfig::He(domain_config) => he::update_domains(ctx, domain_config, addr)
             .await
             .map_err(|e| e.into()),
-        DomainConfig::NoIp(domain_config) => noip::update_domains(http_client, domain_config, addr)
+        DomainConfig::NoIp(domain_config) => noip::update_domains(ctx, domain_config, addr)
             .await
             .map_err(|e| e.into()),
-        DomainConfig::Dynu(domain_config) => dynu::update_domains(http_client, domain_config, addr)
+        DomainConfig::Dynu(domain_config) => dynu::update_domains(ctx, domain_config, addr)
             .await
             .map_err(|e| e.into()),
-        DomainConfig::Porkbun(domain_config) => {
-            porkbun::update_domains(http_client, domain_config, addr)
-                .await
-                .map_err(|e| e.into())
+        DomainConfig::Porkbun(domain_config) => porkbun::update_domains(ctx, domain_config, addr)
+            .await
+            .map_err(|e| e.into()),
+        DomainConfig::Rfc2136(domain_config) => rfc2136::update_domains(ctx, domain_config, addr)
+            .await
+            .map_err(|e| e.into()),
+    }
+}
+
+/// Resolves when the process is asked to stop: SIGINT or SIGTERM on unix and ctrl-c elsewhere.
+/// The signal handlers are installed up front so that a signal received in the middle of an update
+/// cycle is not lost.
+struct Shutdown {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+    #[cfg(unix)]
+    fn install() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Shutdown {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn install() -> std::io::Result<Self> {
+        Ok(Shutdown {})
+    }
+
+    #[cfg(unix)]
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {}
+            _ = self.terminate.recv() => {}
         }
     }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let start = Instant::now();
-    let opt = Opt::parse();
-    let config = init_configuration(opt.config.as_ref());
+/// Loads the state file if one is configured. An unreadable state file is not fatal, as the
+/// worst case is that every record is checked with its provider.
+fn init_state(config: &DnsConfig) -> Option<State> {
+    let path = config.state_file.as_ref()?;
+    match State::load(path) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            let desc = format!("ignoring state file: {}", path.display());
+            log_err(&desc, Box::new(e));
+            Some(State::default())
+        }
+    }
+}
+
+/// What is shared by the concurrent domain updates of a cycle
+struct Cycle<'a> {
+    ctx: &'a Context<'a>,
+    config: &'a DnsConfig,
+    metrics: &'a Metrics,
+    output: Output,
 
-    init_logging(config.log.level);
+    /// How many domains are updated at once, overall and per provider
+    limits: &'a Limits,
+}
 
-    // Use a single HTTP client when updating dns records so that connections can be reused
-    let http_client = reqwest::Client::new();
+/// Everything an update cycle needs that stays the same from one cycle to the next
+struct CycleInputs<'a> {
+    ctx: &'a Context<'a>,
+    ip_resolver: &'a IpResolvers,
+    ip_types: &'a [IpType],
+    config: &'a DnsConfig,
+    metrics: &'a Metrics,
 
-    let mut ip_types: Vec<IpType> = if config.domains.is_empty() {
-        vec![IpType::V4]
-    } else {
-        config
-            .domains
+    /// Whether to ask the providers even when the state file says the records are current
+    force: bool,
+    output: Output,
+}
+
+struct DomainOutcome {
+    report: DomainReport,
+    changes: Vec<RecordChange>,
+    failure: Option<String>,
+
+    /// Whether every record is known to be current, so that the state file can note it
+    complete: bool,
+}
+
+/// Ensures that the records of a domain point at the address
+async fn update_domain(
+    cycle: &Cycle<'_>,
+    d: &DomainConfig,
+    addr: IpAddr,
+    from_state: bool,
+) -> DomainOutcome {
+    let (ctx, config, metrics) = (cycle.ctx, cycle.config, cycle.metrics);
+    let mut outcome = DomainOutcome {
+        report: DomainReport::new(d, addr),
+        changes: Vec::new(),
+        failure: None,
+        complete: false,
+    };
+
+    if from_state {
+        let updates = Updates {
+            current: d.records().len() as i32,
+            ..Updates::default()
+        };
+        info!(
+            "processed {}: ({}) from state file",
+            d.display_name(),
+            updates
+        );
+        metrics.updated(d, IpType::from(addr), updates, None);
+
+        let unchanged: Vec<_> = d
+            .records()
             .iter()
-            .flat_map(|d| d.get_ip_types())
-            .collect()
+            .map(|record| (record.clone(), RecordStatus::Current))
+            .collect();
+        outcome.report.from_state_file = true;
+        outcome.report.summary = updates;
+        outcome.report.records = RecordReport::collect(addr, &[], &unchanged);
+        return outcome;
+    }
+
+    // Held until the update is done
+    let _permit = cycle.limits.acquire(d.provider()).await;
+
+    // The json report lists the planned changes of a dry run instead
+    let changes = match cycle.output {
+        Output::Text => ChangeLog::default(),
+        Output::Json => ChangeLog::quiet(),
+    };
+    let domain_ctx = Context {
+        changes: &changes,
+        ..*ctx
+    };
+    let start_update = Instant::now();
+    let result = update_provider(&domain_ctx, addr, d).await;
+    let changed = changes.take();
+    outcome.report.records = RecordReport::collect(addr, &changed, &changes.take_unchanged());
+
+    match result {
+        Ok(updates) => {
+            info!(
+                "processed {}: ({}) in {}",
+                d.display_name(),
+                updates,
+                elapsed(start_update)
+            );
+            metrics.updated(d, IpType::from(addr), updates, Some(start_update.elapsed()));
+            outcome.report.summary = updates;
+            outcome.complete = updates.missing == 0 && !ctx.dry_run;
+        }
+        Err(e) => {
+            let msg = format!("could not update {}", d.display_name(),);
+            let chain = error_chain(&msg, e.as_ref());
+            let desc = chain.join(": ");
+            metrics.failed(d, IpType::from(addr));
+            metrics.error(d.provider(), metrics::error_kind(e.as_ref()));
+            log_err(&msg, e);
+            if !ctx.dry_run {
+                hooks::on_failure(config, Some(d), &desc).await;
+            }
+            outcome.failure = Some(desc);
+            outcome.report.error = Some(chain);
+        }
+    }
+
+    // Records changed before a failure still get their hook
+    if !ctx.dry_run {
+        hooks::on_update(config, d, &changed).await;
+        outcome.changes = changed;
+    }
+
+    outcome
+}
+
+/// Resolves the WAN IP and ensures that every configured domain points to it. Returns a report of
+/// the cycle, which notes whether any part of it failed so that the caller can decide on the exit
+/// code
+async fn update_cycle(inputs: &CycleInputs<'_>, mut state: Option<&mut State>) -> Report {
+    let CycleInputs {
+        ctx,
+        ip_resolver,
+        ip_types,
+        config,
+        metrics,
+        force,
+        output,
+    } = *inputs;
+    let start = Instant::now();
+    let mut report = Report {
+        dry_run: ctx.dry_run,
+        ..Report::default()
     };
-    ip_types.sort_unstable();
-    ip_types.dedup();
-    let ip_types = ip_types;
 
     // Keep track of any failures in ensuring current DNS records. We don't want to fail on the
     // first error, as subsequent domains listed in the config can still be valid, but if there
-    // were any failures, we still need to exit with a non-zero exit code
-    let mut failure = false;
+    // were any failures, we still need to exit with a non-zero exit code (and send notifications)
+    let mut failures: Vec<String> = Vec::new();
+    let mut cycle_changes = Vec::new();
 
-    let addrs: Vec<Option<IpAddr>> =
+    let resolved: Vec<Result<IpAddr, Vec<String>>> =
         futures::future::join_all(ip_types.iter().map(async |ip_type| {
             let start_resolve = Instant::now();
-            match resolve_ip(&http_client, &config, *ip_type).await {
+            match ip_resolver.resolve(ctx.http, *ip_type).await {
                 Ok(addr) => {
                     info!("resolved address to {} in {}", addr, elapsed(start_resolve));
-                    Some(addr)
+                    metrics.resolved(*ip_type, addr, start_resolve.elapsed());
+                    Ok(addr)
                 }
                 Err(e) => {
+                    metrics.error(metrics::RESOLVER_SOURCE, e.kind_name());
+                    let chain = error_chain("could not successfully resolve IP", &e);
                     log_err("could not successfully resolve IP", Box::new(e));
-                    None
+                    Err(chain)
                 }
             }
         }))
         .await;
-    if addrs.iter().any(Option::is_none) {
-        failure = true;
+
+    let mut addrs = Vec::new();
+    for (ip_type, result) in ip_types.iter().zip(resolved) {
+        match &result {
+            Ok(addr) => addrs.push(*addr),
+            Err(chain) => {
+                let desc = chain.join(": ");
+                if !ctx.dry_run {
+                    hooks::on_failure(config, None, &desc).await;
+                }
+                failures.push(desc);
+            }
+        }
+        report.addresses.push(AddressReport::new(*ip_type, result));
     }
-    let addrs: Vec<IpAddr> = addrs.iter().copied().flatten().collect();
 
     let mut total_updates = Updates::default();
+    let mut state_changed = false;
 
-    for d in config.domains {
-        let ip_types = d.get_ip_types();
-        for addr in addrs.iter() {
-            if !ip_types.contains(&IpType::from(*addr)) {
+    // Each group of records is checked against the state file up front, as the updates below run
+    // concurrently and only borrow the state
+    let mut jobs = Vec::new();
+    for (block, domain) in config.domains.iter().enumerate() {
+        let ip_types = domain.get_ip_types();
+        for resolved in addrs.iter() {
+            if !ip_types.contains(&IpType::from(*resolved)) {
                 continue;
             }
-            let start_update = Instant::now();
-            match update_provider(&http_client, *addr, &d).await {
-                Ok(updates) => {
-                    info!(
-                        "processed {}: ({}) in {}",
-                        d.display_name(),
-                        updates,
-                        elapsed(start_update)
-                    );
-                    total_updates += updates;
-                }
-                Err(e) => {
-                    failure = true;
-                    let msg = format!("could not update {}", d.display_name(),);
-                    log_err(&msg, e);
-                }
+
+            // Records with an IPv6 suffix point at their own address, so are updated separately
+            for (d, addr) in prefix::address_groups(domain, *resolved) {
+                let from_state = !force
+                    && state
+                        .as_ref()
+                        .is_some_and(|s| s.is_current(block, &d, addr));
+                jobs.push((block, d, addr, from_state));
             }
         }
     }
 
+    let limits = Limits::new(config);
+    let cycle = Cycle {
+        ctx,
+        config,
+        metrics,
+        output,
+        limits: &limits,
+    };
+
+    // Every update is started at once and waits for its turn under the limits. Results are
+    // collected in the order of the config so that the report and totals are stable.
+    let outcomes: Vec<DomainOutcome> = futures::future::join_all(
+        jobs.iter()
+            .map(|(_, d, addr, from_state)| update_domain(&cycle, d, *addr, *from_state)),
+    )
+    .await;
+
+    for ((block, d, addr, _), outcome) in jobs.iter().zip(outcomes) {
+        total_updates += outcome.report.summary;
+        cycle_changes.extend(outcome.changes);
+        failures.extend(outcome.failure);
+
+        // Only when every record is accounted for (and was actually pushed) can we skip the
+        // provider next time
+        if let (Some(state), true) = (state.as_mut(), outcome.complete) {
+            if !state.is_current(*block, d, *addr) {
+                state.record(*block, d, *addr);
+                state_changed = true;
+            }
+        }
+
+        report.domains.push(outcome.report);
+    }
+
+    if let (Some(state), Some(path)) = (state, &config.state_file) {
+        if state_changed {
+            if let Err(e) = state.save(path) {
+                let desc = format!("could not save state file: {}", path.display());
+                let chain = error_chain(&desc, &e);
+                failures.push(chain.join(": "));
+                report.errors.push(chain);
+                log_err(&desc, Box::new(e));
+            }
+        }
+    }
+
+    if ctx.dry_run {
+        info!("dry run: no records were changed");
+    } else {
+        let notification = Notification {
+            updates: total_updates,
+            changes: &cycle_changes,
+            failures: &failures,
+        };
+        notify::notify(ctx.http, &config.notify, &notification).await;
+    }
+
+    metrics.cycle_finished();
+    if let (Some(path), false) = (&config.metrics.textfile, ctx.dry_run) {
+        if let Err(e) = metrics.write_textfile(path) {
+            let desc = format!("could not write metrics textfile: {}", path.display());
+            log_err(&desc, Box::new(e));
+        }
+    }
+
     info!("processed all: ({}) in {}", total_updates, elapsed(start));
-    if failure {
-        error!("at least one update failed, so exiting with non-zero status code");
-        std::process::exit(1)
+    report.summary = total_updates;
+    report.success = failures.is_empty();
+    report
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::parse();
+    let config = init_configuration(opt.config.as_ref(), opt.output);
+
+    init_logging(config.log.level, opt.output);
+
+    // Settings like a zero timeout or interval would otherwise fail every request or spin. The
+    // check subcommand lists these along with its other findings instead.
+    if opt.command.is_none() {
+        let problems = check::validate_config(&config);
+        for problem in &problems {
+            error!("invalid configuration: {}", problem);
+        }
+        if !problems.is_empty() {
+            std::process::exit(1)
+        }
     }
+
+    // Use a single HTTP client and dns resolver when updating dns records so that connections can
+    // be reused
+    let http_client = match http_client::create(&config.http) {
+        Ok(client) => client,
+        Err(e) => {
+            log_err("could not create http client", Box::new(e));
+            std::process::exit(1)
+        }
+    };
+    let resolver = match DnsResolver::create_cloudflare(config.lookup.transport).await {
+        Ok(resolver) => resolver.with_retry(&config.retry),
+        Err(e) => {
+            log_err("could not create dns resolver", Box::new(e));
+            std::process::exit(1)
+        }
+    };
+    let ctx = Context {
+        http: &http_client,
+        resolver: &resolver,
+        retry: &config.retry,
+        lookup: &config.lookup,
+        name_servers: &NameServers::default(),
+        dry_run: opt.dry_run,
+        changes: &ChangeLog::default(),
+    };
+
+    if let Some(Command::Check { online }) = opt.command {
+        let mut general = Vec::new();
+        if let Err(e) = IpResolvers::create(&config).await {
+            general.push(e.to_string());
+        }
+
+        let reports = check::check(&ctx, &config, general, online).await;
+        let problems: usize = reports.iter().map(|x| x.problems.len()).sum();
+        for report in &reports {
+            report.print();
+        }
+
+        if problems != 0 {
+            println!("found {} problem(s)", problems);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let ip_resolver = match IpResolvers::create(&config).await {
+        Ok(ip_resolver) => ip_resolver,
+        Err(e) => {
+            log_err("could not create ip resolver", Box::new(e));
+            std::process::exit(1)
+        }
+    };
+
+    let mut ip_types: Vec<IpType> = if config.domains.is_empty() {
+        vec![IpType::V4]
+    } else {
+        config
+            .domains
+            .iter()
+            .flat_map(|d| d.get_ip_types())
+            .collect()
+    };
+    ip_types.sort_unstable();
+    ip_types.dedup();
+    let ip_types = ip_types;
+    let mut state = init_state(&config);
+    let metrics = Arc::new(Metrics::default());
+    let inputs = CycleInputs {
+        ctx: &ctx,
+        ip_resolver: &ip_resolver,
+        ip_types: &ip_types,
+        config: &config,
+        metrics: &metrics,
+        force: opt.force,
+        output: opt.output,
+    };
+
+    if !opt.daemon {
+        let report = update_cycle(&inputs, state.as_mut()).await;
+        print_report(opt.output, &report);
+        if !report.success {
+            error!("at least one update failed, so exiting with non-zero status code");
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let mut shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            log_err("could not install signal handlers", Box::new(e));
+            std::process::exit(1)
+        }
+    };
+
+    if let Some(listen) = &config.metrics.listen {
+        match TcpListener::bind(listen).await {
+            Ok(listener) => {
+                info!("serving metrics on http://{}/metrics", listen);
+                tokio::spawn(metrics::serve(listener, Arc::clone(&metrics)));
+            }
+            Err(e) => {
+                let desc = format!("could not listen for metrics on {}", listen);
+                log_err(&desc, Box::new(e));
+                std::process::exit(1)
+            }
+        }
+    }
+
+    info!("running as a daemon, updating every {}s", config.interval);
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(config.interval));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // A failed cycle is logged and retried on the next tick. A signal received during a cycle lets
+    // it finish, so that the state file is saved and hooks run, and is acted on once idle.
+    loop {
+        // A signal that arrived during the last cycle wins over a tick that is also due
+        tokio::select! {
+            biased;
+            _ = shutdown.recv() => break,
+            _ = ticker.tick() => {}
+        }
+
+        let report = update_cycle(&inputs, state.as_mut()).await;
+        print_report(opt.output, &report);
+    }
+
+    info!("received shutdown signal, exiting");
 }
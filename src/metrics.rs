@@ -0,0 +1,415 @@
+use crate::cloudflare::ClError;
+use crate::config::{DomainConfig, IpType};
+use crate::core::Updates;
+use crate::errors::DnessError;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt::Write;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Where an error came from, as either the WAN IP resolution or the provider of a domain
+pub const RESOLVER_SOURCE: &str = "resolver";
+
+#[derive(Debug, Default)]
+struct DomainMetrics {
+    last_success: Option<f64>,
+    update_seconds: Option<f64>,
+    updated: u64,
+    current: u64,
+    missing: u64,
+    failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    wan: BTreeMap<IpType, IpAddr>,
+    resolve_seconds: BTreeMap<IpType, f64>,
+    /// Keyed by the domain, its provider, and the address family, so that one family failing
+    /// isn't hidden by the other succeeding
+    domains: BTreeMap<(String, &'static str, IpType), DomainMetrics>,
+    errors: BTreeMap<(&'static str, &'static str), u64>,
+    last_cycle: Option<f64>,
+}
+
+/// Metrics collected across update cycles, rendered in the prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn ip_label(ip_type: IpType) -> &'static str {
+    match ip_type {
+        IpType::V4 => "4",
+        IpType::V6 => "6",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The name of the error's kind for the errors that dness defines, and "other" otherwise
+pub fn error_kind(err: &(dyn error::Error + 'static)) -> &'static str {
+    if let Some(e) = err.downcast_ref::<DnessError>() {
+        e.kind_name()
+    } else if let Some(e) = err.downcast_ref::<ClError>() {
+        e.kind_name()
+    } else {
+        "other"
+    }
+}
+
+impl Metrics {
+    pub fn resolved(&self, ip_type: IpType, addr: IpAddr, took: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.wan.insert(ip_type, addr);
+        state.resolve_seconds.insert(ip_type, took.as_secs_f64());
+    }
+
+    fn domain<'a>(
+        state: &'a mut MetricsState,
+        domain: &DomainConfig,
+        ip_type: IpType,
+    ) -> &'a mut DomainMetrics {
+        let key = (String::from(domain.zone()), domain.provider(), ip_type);
+        state.domains.entry(key).or_default()
+    }
+
+    /// Records a successful update of the domain's records of the type. The duration is absent
+    /// when the state file showed the records to be current.
+    pub fn updated(
+        &self,
+        domain: &DomainConfig,
+        ip_type: IpType,
+        updates: Updates,
+        took: Option<Duration>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let metrics = Self::domain(&mut state, domain, ip_type);
+        metrics.last_success = Some(now());
+        if let Some(took) = took {
+            metrics.update_seconds = Some(took.as_secs_f64());
+        }
+        metrics.updated += updates.updated as u64;
+        metrics.current += updates.current as u64;
+        metrics.missing += updates.missing as u64;
+    }
+
+    /// Records a failed update of the domain's records of the type
+    pub fn failed(&self, domain: &DomainConfig, ip_type: IpType) {
+        let mut state = self.state.lock().unwrap();
+        Self::domain(&mut state, domain, ip_type).failures += 1;
+    }
+
+    pub fn error(&self, source: &'static str, kind: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        *state.errors.entry((source, kind)).or_default() += 1;
+    }
+
+    pub fn cycle_finished(&self) {
+        self.state.lock().unwrap().last_cycle = Some(now());
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP dness_wan_ip_info The most recently resolved WAN IP address"
+        );
+        let _ = writeln!(out, "# TYPE dness_wan_ip_info gauge");
+        for (ip_type, addr) in &state.wan {
+            let _ = writeln!(
+                out,
+                "dness_wan_ip_info{{ip_type=\"{}\",address=\"{}\"}} 1",
+                ip_label(*ip_type),
+                addr
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP dness_resolve_duration_seconds How long the last WAN IP resolution took"
+        );
+        let _ = writeln!(out, "# TYPE dness_resolve_duration_seconds gauge");
+        for (ip_type, seconds) in &state.resolve_seconds {
+            let _ = writeln!(
+                out,
+                "dness_resolve_duration_seconds{{ip_type=\"{}\"}} {}",
+                ip_label(*ip_type),
+                seconds
+            );
+        }
+
+        type Field = fn(&DomainMetrics) -> Option<f64>;
+        let domain_metrics: [(&str, &str, &str, Field); 6] = [
+            (
+                "dness_last_success_timestamp_seconds",
+                "gauge",
+                "When the records of the domain were last confirmed to be current",
+                |m| m.last_success,
+            ),
+            (
+                "dness_update_duration_seconds",
+                "gauge",
+                "How long the last update of the domain took",
+                |m| m.update_seconds,
+            ),
+            (
+                "dness_records_updated_total",
+                "counter",
+                "Records that were pointed at a new address",
+                |m| Some(m.updated as f64),
+            ),
+            (
+                "dness_records_current_total",
+                "counter",
+                "Records that were found to be current",
+                |m| Some(m.current as f64),
+            ),
+            (
+                "dness_records_missing_total",
+                "counter",
+                "Records that could not be found",
+                |m| Some(m.missing as f64),
+            ),
+            (
+                "dness_update_failures_total",
+                "counter",
+                "Updates of the domain that failed",
+                |m| Some(m.failures as f64),
+            ),
+        ];
+
+        for (name, kind, help, field) in domain_metrics {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for ((domain, provider, ip_type), metrics) in &state.domains {
+                if let Some(value) = field(metrics) {
+                    let _ = writeln!(
+                        out,
+                        "{}{{domain=\"{}\",provider=\"{}\",ip_type=\"{}\"}} {}",
+                        name,
+                        escape(domain),
+                        provider,
+                        ip_label(*ip_type),
+                        value
+                    );
+                }
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP dness_errors_total Errors by where they occurred"
+        );
+        let _ = writeln!(out, "# TYPE dness_errors_total counter");
+        for ((source, kind), count) in &state.errors {
+            let _ = writeln!(
+                out,
+                "dness_errors_total{{source=\"{}\",kind=\"{}\"}} {}",
+                source, kind, count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP dness_last_cycle_timestamp_seconds When the last update cycle finished"
+        );
+        let _ = writeln!(out, "# TYPE dness_last_cycle_timestamp_seconds gauge");
+        if let Some(last_cycle) = state.last_cycle {
+            let _ = writeln!(out, "dness_last_cycle_timestamp_seconds {}", last_cycle);
+        }
+
+        out
+    }
+
+    /// Writes the metrics through a temporary file so that the textfile collector never reads a
+    /// partially written file
+    pub fn write_textfile(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.render())?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // Only the request line matters, which is expected to fit in the first read
+    let mut buf = [0u8; 1024];
+    let read = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut parts = request.split_whitespace();
+
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::from("not found\n")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves the metrics at "/metrics" until the process exits
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let metrics = Arc::clone(&metrics);
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &metrics).await {
+                        debug!("metrics request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => warn!("could not accept metrics connection: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NoIpConfig;
+    use std::net::Ipv4Addr;
+
+    fn noip_domain() -> DomainConfig {
+        DomainConfig::NoIp(NoIpConfig {
+            base_url: String::from("https://dynupdate.no-ip.com"),
+            hostname: String::from("example.com"),
+            username: String::from("me@example.com"),
+            password: String::from("my-pass"),
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+        })
+    }
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        let addr = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        metrics.resolved(IpType::V4, addr, Duration::from_millis(250));
+        let updates = Updates {
+            updated: 1,
+            ..Updates::default()
+        };
+        let domain = noip_domain();
+        metrics.updated(
+            &domain,
+            IpType::V4,
+            updates,
+            Some(Duration::from_millis(500)),
+        );
+        metrics.updated(&domain, IpType::V4, updates, None);
+        metrics.error("godaddy", "bad_response");
+        metrics.error(RESOLVER_SOURCE, "dns");
+        metrics.error(RESOLVER_SOURCE, "dns");
+
+        let out = metrics.render();
+        let lines: Vec<&str> = out.lines().filter(|l| !l.starts_with('#')).collect();
+        assert!(lines.contains(&"dness_wan_ip_info{ip_type=\"4\",address=\"2.2.2.2\"} 1"));
+        assert!(lines.contains(&"dness_resolve_duration_seconds{ip_type=\"4\"} 0.25"));
+        assert!(lines.contains(
+            &"dness_update_duration_seconds{domain=\"example.com\",provider=\"noip\",ip_type=\"4\"} 0.5"
+        ));
+        assert!(lines.contains(
+            &"dness_records_updated_total{domain=\"example.com\",provider=\"noip\",ip_type=\"4\"} 2"
+        ));
+        assert!(lines.contains(&"dness_errors_total{source=\"godaddy\",kind=\"bad_response\"} 1"));
+        assert!(lines.contains(&"dness_errors_total{source=\"resolver\",kind=\"dns\"} 2"));
+        assert!(lines.iter().any(|l| l.starts_with(
+            "dness_last_success_timestamp_seconds{domain=\"example.com\",provider=\"noip\",ip_type=\"4\"} "
+        )));
+    }
+
+    #[test]
+    fn render_one_family_failing() {
+        let metrics = Metrics::default();
+        let domain = noip_domain();
+        metrics.updated(&domain, IpType::V4, Updates::default(), None);
+        metrics.failed(&domain, IpType::V6);
+        metrics.failed(&domain, IpType::V6);
+
+        let out = metrics.render();
+        let lines: Vec<&str> = out.lines().filter(|l| !l.starts_with('#')).collect();
+        let labels = |ip_type| {
+            format!(
+                "{{domain=\"example.com\",provider=\"noip\",ip_type=\"{}\"}}",
+                ip_type
+            )
+        };
+
+        // The AAAA records were never confirmed, so they show up as stale
+        let last_success = |ip_type| {
+            let prefix = format!("dness_last_success_timestamp_seconds{} ", labels(ip_type));
+            lines.iter().any(|l| l.starts_with(&prefix))
+        };
+        assert!(last_success("4"));
+        assert!(!last_success("6"));
+
+        let failures = format!("dness_update_failures_total{} 2", labels("6"));
+        assert!(lines.contains(&failures.as_str()));
+        let failures = format!("dness_update_failures_total{} 0", labels("4"));
+        assert!(lines.contains(&failures.as_str()));
+    }
+
+    #[test]
+    fn classify_errors() {
+        let err = DnessError::message(String::from("oops"));
+        assert_eq!(error_kind(&err), "message");
+        let err = io::Error::other("oops");
+        assert_eq!(error_kind(&err), "other");
+    }
+
+    #[tokio::test]
+    async fn serve_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.error(RESOLVER_SOURCE, "stun");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&metrics)));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = response.text().await.unwrap();
+        assert!(body.contains("dness_errors_total{source=\"resolver\",kind=\"stun\"} 1\n"));
+
+        let response = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -1,13 +1,15 @@
-use crate::config::NamecheapConfig;
-use crate::core::Updates;
-use crate::dns::DnsResolver;
+use crate::authoritative;
+use crate::config::{NamecheapConfig, RetryConfig};
+use crate::core::{fqdn, Context, Updates};
 use crate::errors::DnessError;
+use crate::retry::SendRetry;
 use log::{info, warn};
 use std::net::{IpAddr, Ipv4Addr};
 
 #[derive(Debug)]
 pub struct NamecheapProvider<'a> {
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
     config: &'a NamecheapConfig,
 }
 
@@ -25,7 +27,7 @@ impl NamecheapProvider<'_> {
                 ("password", &self.config.ddns_password),
                 ("ip", &wan.to_string()),
             ])
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&get_url, "namecheap update", e))?
             .error_for_status()
@@ -45,14 +47,26 @@ impl NamecheapProvider<'_> {
     }
 }
 
+/// Returns the configured records that can't be resolved. The credentials can't be verified as the
+/// update endpoint is the only authenticated api.
+pub async fn missing_records(ctx: &Context<'_>, config: &NamecheapConfig) -> Vec<String> {
+    let mut missing = Vec::new();
+    for record in &config.records {
+        let dns_query = format!("{}.", fqdn(record, &config.domain));
+        if ctx.resolver.ipv4_lookup(&dns_query).await.is_err() {
+            missing.push(record.clone());
+        }
+    }
+    missing
+}
+
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &NamecheapConfig,
     wan: IpAddr,
 ) -> Result<Updates, DnessError> {
-    // Use cloudflare's DNS to query all the configured records. Ideally we'd use dns
-    // over tls for privacy purposes but that feature is experimental and we don't want to rely on
-    // experimental features here: https://github.com/bluejekyll/trust-dns/issues/989
+    // Use cloudflare's DNS (or the zone's authoritative name servers) to query all the configured
+    // records, over tls or https when configured for privacy.
     //
     // We check all the records with DNS before issuing any requests to update them in namecheap so
     // that we can be a good netizen. One issue seen with this approach is that in subsequent
@@ -64,26 +78,34 @@ pub async fn update_domains(
             "IPv6 not supported for Namecheap",
         )));
     };
-    let resolver = DnsResolver::create_cloudflare().await?;
-    let namecheap = NamecheapProvider { client, config };
+    let namecheap = NamecheapProvider {
+        client: ctx.http,
+        retry: ctx.retry,
+        config,
+    };
 
     let mut results = Updates::default();
+    let mut updated = Vec::new();
 
     for record in &config.records {
-        let dns_query = if record == "@" {
-            format!("{}.", config.domain)
-        } else {
-            format!("{}.{}.", record, config.domain)
-        };
-
-        let response = resolver.ipv4_lookup(&dns_query).await;
+        let host = fqdn(record, &config.domain);
+        let dns_query = format!("{}.", host);
+        let response = authoritative::current_ip(ctx, &dns_query, wan.into()).await;
 
         match response {
             Ok(ip) => {
-                if ip == wan {
+                if ip == IpAddr::V4(wan) {
                     results.current += 1;
+                    ctx.changes.current(&host);
+                } else if ctx.dry_run {
+                    ctx.changes
+                        .plan(&host, &config.domain, &ip.to_string(), wan.into());
+                    results.updated += 1;
                 } else {
                     namecheap.update_domain(record, wan).await?;
+                    updated.push(host.clone());
+                    ctx.changes
+                        .push(&host, &config.domain, &ip.to_string(), wan.into());
                     info!(
                         "{} from domain {} updated from {} to {}",
                         record, config.domain, ip, wan
@@ -91,23 +113,26 @@ pub async fn update_domains(
                     results.updated += 1;
                 }
             }
+            Err(e) if !e.is_not_found() => return Err(e.into()),
             Err(e) => {
-                // Could be a network issue or it could be that the record didn't exist.
                 warn!(
                     "resolving namecheap record ({}) encountered an error: {}",
                     record, e
                 );
                 results.missing += 1;
+                ctx.changes.missing(&host);
             }
         }
     }
 
+    authoritative::verify(ctx, &updated, wan.into()).await?;
     Ok(results)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dns::DnsResolver;
 
     macro_rules! namecheap_server {
         () => {{
@@ -139,15 +164,20 @@ mod tests {
     async fn test_namecheap_update() {
         let (tx, addr) = namecheap_server!();
         let http_client = reqwest::Client::new();
+        let old_ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let resolver = DnsResolver::for_test(old_ip).await;
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = NamecheapConfig {
             base_url: format!("http://{}", addr),
             domain: String::from("example.com"),
             ddns_password: String::from("secret-1"),
             records: vec![String::from("@")],
+            on_update: None,
+            on_failure: None,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
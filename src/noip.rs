@@ -1,10 +1,17 @@
-use crate::{config::NoIpConfig, core::Updates, dns::DnsResolver, errors::DnessError};
+use crate::{
+    authoritative,
+    config::{IpType, NoIpConfig, RetryConfig},
+    core::{Context, Updates},
+    errors::DnessError,
+    retry::SendRetry,
+};
 use log::{info, warn};
 use std::net::IpAddr;
 
 #[derive(Debug)]
 pub struct NoIpProvider<'a> {
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
     config: &'a NoIpConfig,
 }
 
@@ -21,7 +28,7 @@ impl NoIpProvider<'_> {
                 ("myip", &wan.to_string()),
             ])
             .basic_auth(&self.config.username, Some(&self.config.password))
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&get_url, "noip update", e))?
             .error_for_status()
@@ -41,37 +48,66 @@ impl NoIpProvider<'_> {
     }
 }
 
+/// Returns the hostname if it can't be resolved. The credentials can't be verified as the update
+/// endpoint is the only authenticated api.
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &NoIpConfig,
+    ip_type: IpType,
+) -> Vec<String> {
+    let dns_query = format!("{}.", &config.hostname);
+    match ctx.resolver.ip_lookup(&dns_query, ip_type).await {
+        Ok(_) => Vec::new(),
+        Err(_) => vec![config.hostname.clone()],
+    }
+}
+
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &NoIpConfig,
     wan: IpAddr,
 ) -> Result<Updates, DnessError> {
-    let resolver = DnsResolver::create_cloudflare().await?;
     let dns_query = format!("{}.", &config.hostname);
-    let response = resolver.ip_lookup(&dns_query, wan.into()).await;
-    let provider = NoIpProvider { client, config };
+    let response = authoritative::current_ip(ctx, &dns_query, wan).await;
+    let provider = NoIpProvider {
+        client: ctx.http,
+        retry: ctx.retry,
+        config,
+    };
     match response {
         Ok(ip) => {
             if ip == wan {
+                ctx.changes.current(&config.hostname);
                 Ok(Updates {
                     current: 1,
                     ..Updates::default()
                 })
+            } else if ctx.dry_run {
+                ctx.changes
+                    .plan(&config.hostname, &config.hostname, &ip.to_string(), wan);
+                Ok(Updates {
+                    updated: 1,
+                    ..Updates::default()
+                })
             } else {
                 provider.update_domain(wan).await?;
+                ctx.changes
+                    .push(&config.hostname, &config.hostname, &ip.to_string(), wan);
                 info!("{} updated from {} to {}", config.hostname, ip, wan);
+                authoritative::verify(ctx, std::slice::from_ref(&config.hostname), wan).await?;
                 Ok(Updates {
                     updated: 1,
                     ..Updates::default()
                 })
             }
         }
+        Err(e) if !e.is_not_found() => Err(e.into()),
         Err(e) => {
-            // Could be a network issue or it could be that the record didn't exist.
             warn!(
                 "resolving noip ({}) encountered an error: {}",
                 config.hostname, e
             );
+            ctx.changes.missing(&config.hostname);
             Ok(Updates {
                 missing: 1,
                 ..Updates::default()
@@ -83,7 +119,7 @@ pub async fn update_domains(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::IpType;
+    use crate::dns::DnsResolver;
     use std::net::Ipv4Addr;
 
     macro_rules! noip_server {
@@ -113,6 +149,9 @@ mod tests {
     async fn test_noip_update() {
         let (tx, addr) = noip_server!();
         let http_client = reqwest::Client::new();
+        let old_ip = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let resolver = DnsResolver::for_test(old_ip).await;
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = NoIpConfig {
             base_url: format!("http://{}", addr),
@@ -120,9 +159,11 @@ mod tests {
             username: String::from("me@example.com"),
             password: String::from("my-pass"),
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
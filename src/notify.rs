@@ -0,0 +1,548 @@
+use crate::config::{
+    GotifyConfig, IpType, NotifyConfig, NotifyOn, NtfyConfig, SmtpConfig, SmtpTls, WebhookConfig,
+};
+use crate::core::{RecordChange, Updates};
+use crate::errors::{describe_error, DnessError};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{debug, warn};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// What happened during an update cycle
+#[derive(Debug, Clone, Copy)]
+pub struct Notification<'a> {
+    pub updates: Updates,
+    pub changes: &'a [RecordChange],
+    pub failures: &'a [String],
+}
+
+impl<'a> Notification<'a> {
+    /// The parts of the notification that a backend asked for, if there is anything to send
+    fn events(&self, on: NotifyOn) -> Option<Notification<'a>> {
+        let changes = if on.change() { self.changes } else { &[] };
+        let failures = if on.failure() { self.failures } else { &[] };
+        if changes.is_empty() && failures.is_empty() {
+            None
+        } else {
+            Some(Notification {
+                updates: self.updates,
+                changes,
+                failures,
+            })
+        }
+    }
+
+    fn title(&self) -> String {
+        let mut parts = Vec::new();
+        match self.changes.len() {
+            0 => {}
+            1 => parts.push(String::from("1 record updated")),
+            n => parts.push(format!("{} records updated", n)),
+        }
+        match self.failures.len() {
+            0 => {}
+            1 => parts.push(String::from("1 failure")),
+            n => parts.push(format!("{} failures", n)),
+        }
+        format!("dness: {}", parts.join(", "))
+    }
+
+    /// One line per changed record and failure, followed by the summary of the cycle
+    fn message(&self) -> String {
+        let mut msg = String::new();
+        for change in self.changes {
+            let old = if change.old.is_empty() {
+                "(created)"
+            } else {
+                change.old.as_str()
+            };
+            let _ = writeln!(
+                msg,
+                "{} {} {} -> {}",
+                change.record,
+                record_type(change),
+                old,
+                change.new
+            );
+        }
+
+        for failure in self.failures {
+            let _ = writeln!(msg, "{}", failure);
+        }
+
+        let _ = write!(msg, "{}", self.updates);
+        msg
+    }
+}
+
+fn record_type(change: &RecordChange) -> &'static str {
+    IpType::from(change.new).record_type()
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    title: String,
+    message: String,
+    summary: Updates,
+    records: Vec<WebhookRecord<'a>>,
+    failures: &'a [String],
+}
+
+#[derive(Serialize)]
+struct WebhookRecord<'a> {
+    record: &'a str,
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    zone: &'a str,
+    old: &'a str,
+    new: String,
+}
+
+async fn send_webhook(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    notification: &Notification<'_>,
+) -> Result<(), DnessError> {
+    let payload = WebhookPayload {
+        title: notification.title(),
+        message: notification.message(),
+        summary: notification.updates,
+        records: notification
+            .changes
+            .iter()
+            .map(|change| WebhookRecord {
+                record: &change.record,
+                record_type: record_type(change),
+                zone: &change.zone,
+                old: &change.old,
+                new: change.new.to_string(),
+            })
+            .collect(),
+        failures: notification.failures,
+    };
+
+    let mut request = client.post(&config.url).json(&payload);
+    for (key, value) in &config.headers {
+        request = request.header(key.as_str(), value.as_str());
+    }
+
+    request
+        .send()
+        .await
+        .map_err(|e| DnessError::send_http(&config.url, "webhook notification", e))?
+        .error_for_status()
+        .map_err(|e| DnessError::bad_response(&config.url, "webhook notification", e))?;
+    Ok(())
+}
+
+/// https://docs.ntfy.sh/publish/
+async fn send_ntfy(
+    client: &reqwest::Client,
+    config: &NtfyConfig,
+    notification: &Notification<'_>,
+) -> Result<(), DnessError> {
+    let url = format!("{}/{}", config.base_url.trim_end_matches('/'), config.topic);
+
+    let tags = if notification.failures.is_empty() {
+        "globe_with_meridians"
+    } else {
+        "warning"
+    };
+
+    let mut request = client
+        .post(&url)
+        .header("Title", notification.title())
+        .header("Tags", tags)
+        .body(notification.message());
+
+    if let Some(priority) = config.priority {
+        request = request.header("Priority", priority.to_string());
+    }
+
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+
+    request
+        .send()
+        .await
+        .map_err(|e| DnessError::send_http(&url, "ntfy notification", e))?
+        .error_for_status()
+        .map_err(|e| DnessError::bad_response(&url, "ntfy notification", e))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct GotifyMessage {
+    title: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u8>,
+}
+
+/// https://gotify.net/docs/pushmsg
+async fn send_gotify(
+    client: &reqwest::Client,
+    config: &GotifyConfig,
+    notification: &Notification<'_>,
+) -> Result<(), DnessError> {
+    let url = format!("{}/message", config.base_url.trim_end_matches('/'));
+    let message = GotifyMessage {
+        title: notification.title(),
+        message: notification.message(),
+        priority: config.priority,
+    };
+
+    client
+        .post(&url)
+        .header("X-Gotify-Key", &config.token)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| DnessError::send_http(&url, "gotify notification", e))?
+        .error_for_status()
+        .map_err(|e| DnessError::bad_response(&url, "gotify notification", e))?;
+    Ok(())
+}
+
+fn mailbox(addr: &str) -> Result<Mailbox, DnessError> {
+    addr.parse()
+        .map_err(|e| DnessError::message(format!("invalid email address {}: {}", addr, e)))
+}
+
+async fn send_smtp(config: &SmtpConfig, notification: &Notification<'_>) -> Result<(), DnessError> {
+    let mut email = Message::builder()
+        .from(mailbox(&config.from)?)
+        .subject(notification.title())
+        .header(ContentType::TEXT_PLAIN);
+    for to in &config.to {
+        email = email.to(mailbox(to)?);
+    }
+
+    let email = email
+        .body(notification.message())
+        .map_err(|e| DnessError::message(format!("unable to create email: {}", e)))?;
+
+    let mut transport = match config.tls {
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+    };
+
+    if let Some(port) = config.port {
+        transport = transport.port(port);
+    }
+
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    transport.build().send(email).await?;
+    Ok(())
+}
+
+async fn send(
+    client: &reqwest::Client,
+    config: &NotifyConfig,
+    notification: &Notification<'_>,
+) -> Result<(), DnessError> {
+    match config {
+        NotifyConfig::Webhook(c) => send_webhook(client, c, notification).await,
+        NotifyConfig::Ntfy(c) => send_ntfy(client, c, notification).await,
+        NotifyConfig::Gotify(c) => send_gotify(client, c, notification).await,
+        NotifyConfig::Smtp(c) => send_smtp(c, notification).await,
+    }
+}
+
+/// Sends the notification to every backend interested in its events. A backend that can't be
+/// reached is only logged, as the records themselves were still updated.
+pub async fn notify(
+    client: &reqwest::Client,
+    configs: &[NotifyConfig],
+    notification: &Notification<'_>,
+) {
+    let sends = configs.iter().filter_map(|config| {
+        let events = notification.events(config.on())?;
+        Some(async move {
+            match send(client, config, &events).await {
+                Ok(()) => debug!("sent notification: {}", events.title()),
+                Err(e) => warn!("{}", describe_error("could not send notification", &e)),
+            }
+        })
+    });
+
+    futures::future::join_all(sends).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::{Arc, Mutex};
+
+    macro_rules! capture_server {
+        ($captured:expr) => {{
+            use rouille::{Request, Response, Server};
+            use std::io::Read;
+
+            let captured = $captured.clone();
+            let server = Server::new("localhost:0", move |request: &Request| {
+                let mut body = String::new();
+                request.data().unwrap().read_to_string(&mut body).unwrap();
+                let headers = request
+                    .headers()
+                    .map(|(k, v)| (k.to_lowercase(), String::from(v)))
+                    .collect::<BTreeMap<_, _>>();
+                captured
+                    .lock()
+                    .unwrap()
+                    .push((request.url(), headers, body));
+                Response::text("ok")
+            })
+            .unwrap();
+
+            let (tx, rx) = std::sync::mpsc::sync_channel(1);
+            let addr = server.server_addr().clone();
+            std::thread::spawn(move || {
+                while let Err(_) = rx.try_recv() {
+                    server.poll();
+                    std::thread::sleep(std::time::Duration::from_millis(50))
+                }
+            });
+            (tx, addr)
+        }};
+    }
+
+    type Captured = Arc<Mutex<Vec<(String, BTreeMap<String, String>, String)>>>;
+
+    fn changes() -> Vec<RecordChange> {
+        vec![
+            RecordChange {
+                record: String::from("www.example.com"),
+                zone: String::from("example.com"),
+                old: String::from("1.1.1.1"),
+                new: IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)),
+            },
+            RecordChange {
+                record: String::from("nas.example.com"),
+                zone: String::from("example.com"),
+                old: String::new(),
+                new: IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)),
+            },
+        ]
+    }
+
+    #[test]
+    fn notification_events() {
+        let changes = changes();
+        let failures = vec![String::from(
+            "could not update example.org (godaddy): bad key",
+        )];
+        let notification = Notification {
+            updates: Updates {
+                updated: 2,
+                current: 1,
+                missing: 0,
+            },
+            changes: &changes,
+            failures: &failures,
+        };
+
+        assert_eq!(notification.title(), "dness: 2 records updated, 1 failure");
+        assert_eq!(
+            notification.message(),
+            "www.example.com A 1.1.1.1 -> 2.2.2.2\n\
+             nas.example.com A (created) -> 2.2.2.2\n\
+             could not update example.org (godaddy): bad key\n\
+             updated: 2, already current: 1, missing: 0"
+        );
+
+        let failed = notification.events(NotifyOn::Failure).unwrap();
+        assert_eq!(failed.title(), "dness: 1 failure");
+        assert!(failed.changes.is_empty());
+
+        let quiet = Notification {
+            failures: &[],
+            ..notification
+        };
+        assert!(quiet.events(NotifyOn::Failure).is_none());
+        assert!(quiet.events(NotifyOn::Change).is_some());
+    }
+
+    #[tokio::test]
+    async fn notify_webhook_and_ntfy() {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let (tx, addr) = capture_server!(captured);
+
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("X-Secret"), String::from("abc"));
+        let configs = vec![
+            NotifyConfig::Webhook(WebhookConfig {
+                url: format!("http://{}/hook", addr),
+                headers,
+                on: NotifyOn::Both,
+            }),
+            NotifyConfig::Ntfy(NtfyConfig {
+                base_url: format!("http://{}/", addr),
+                topic: String::from("dness"),
+                token: Some(String::from("tk_123")),
+                priority: Some(4),
+                on: NotifyOn::Change,
+            }),
+            NotifyConfig::Gotify(GotifyConfig {
+                base_url: format!("http://{}", addr),
+                token: String::from("app-token"),
+                priority: None,
+                on: NotifyOn::Failure,
+            }),
+        ];
+
+        let changes = changes();
+        let notification = Notification {
+            updates: Updates {
+                updated: 2,
+                current: 0,
+                missing: 0,
+            },
+            changes: &changes,
+            failures: &[],
+        };
+
+        let http_client = reqwest::Client::new();
+        notify(&http_client, &configs, &notification).await;
+        tx.send(()).unwrap();
+
+        let mut captured = captured.lock().unwrap().clone();
+        captured.sort();
+
+        // Gotify is only interested in failures, so isn't sent anything
+        assert_eq!(captured.len(), 2);
+
+        let (url, headers, body) = &captured[0];
+        assert_eq!(url, "/dness");
+        assert_eq!(headers["title"], "dness: 2 records updated");
+        assert_eq!(headers["priority"], "4");
+        assert_eq!(headers["authorization"], "Bearer tk_123");
+        assert!(body.starts_with("www.example.com A 1.1.1.1 -> 2.2.2.2\n"));
+
+        let (url, headers, body) = &captured[1];
+        assert_eq!(url, "/hook");
+        assert_eq!(headers["x-secret"], "abc");
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["summary"]["updated"], 2);
+        assert_eq!(payload["records"][1]["record"], "nas.example.com");
+        assert_eq!(payload["records"][1]["type"], "A");
+        assert_eq!(payload["records"][1]["old"], "");
+        assert_eq!(payload["records"][1]["new"], "2.2.2.2");
+    }
+
+    /// An smtp server that accepts a single message and returns the commands it was sent,
+    /// followed by the message data
+    async fn smtp_server() -> (std::net::SocketAddr, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut transcript = Vec::new();
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = if line == "DATA" {
+                    writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                    let mut data = Vec::new();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        data.push(line);
+                    }
+                    transcript.push(data.join("\n"));
+                    b"250 queued\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    transcript.push(line);
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            transcript
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn notify_smtp() {
+        let (addr, server) = smtp_server().await;
+        let configs = vec![NotifyConfig::Smtp(SmtpConfig {
+            host: addr.ip().to_string(),
+            port: Some(addr.port()),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: String::from("dness <dness@example.com>"),
+            to: vec![
+                String::from("admin@example.com"),
+                String::from("ops@example.com"),
+            ],
+            on: NotifyOn::Both,
+        })];
+
+        let changes = changes();
+        let notification = Notification {
+            updates: Updates {
+                updated: 2,
+                current: 0,
+                missing: 0,
+            },
+            changes: &changes,
+            failures: &[],
+        };
+
+        let http_client = reqwest::Client::new();
+        notify(&http_client, &configs, &notification).await;
+        let transcript = server.await.unwrap();
+
+        assert!(transcript[0].starts_with("EHLO "));
+        assert_eq!(
+            &transcript[1..4],
+            [
+                "MAIL FROM:<dness@example.com>",
+                "RCPT TO:<admin@example.com>",
+                "RCPT TO:<ops@example.com>",
+            ]
+        );
+
+        let data = &transcript[4];
+        assert!(
+            data.contains("Subject: dness: 2 records updated"),
+            "{}",
+            data
+        );
+        assert!(
+            data.contains("To: admin@example.com, ops@example.com"),
+            "{}",
+            data
+        );
+        assert!(
+            data.contains("www.example.com A 1.1.1.1 -> 2.2.2.2"),
+            "{}",
+            data
+        );
+        assert!(
+            data.contains("nas.example.com A (created) -> 2.2.2.2"),
+            "{}",
+            data
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use crate::config::IpType;
-use crate::config::PorkbunConfig;
-use crate::core::Updates;
+use crate::config::{PorkbunConfig, RetryConfig};
+use crate::core::{ChangeLog, Context, Updates};
 use crate::errors::DnessError;
+use crate::retry::SendRetry;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -53,21 +54,65 @@ struct PorkbunClient<'a> {
     secret: String,
     records: HashSet<String>,
     client: &'a reqwest::Client,
+    retry: &'a RetryConfig,
+    create_ttl: Option<u32>,
+    dry_run: bool,
+    changes: &'a ChangeLog,
 }
 
 impl PorkbunClient<'_> {
+    fn create<'b>(ctx: &Context<'b>, config: &PorkbunConfig) -> PorkbunClient<'b> {
+        PorkbunClient {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            domain: config.domain.clone(),
+            key: config.key.clone(),
+            secret: config.secret.clone(),
+            records: config
+                .records
+                .iter()
+                .map(|r| {
+                    // To be consistent with other dns providers we allow the user to use '@' for
+                    // root domain. Porkbun uses an empty string, so we map that here.
+                    if r == "@" {
+                        String::from("")
+                    } else {
+                        r.to_string()
+                    }
+                })
+                .collect(),
+            client: ctx.http,
+            retry: ctx.retry,
+            create_ttl: Some(config.create_ttl).filter(|_| config.create_missing),
+            dry_run: ctx.dry_run,
+            changes: ctx.changes,
+        }
+    }
+
     fn strip_domain_from_name(&self, name: &str) -> String {
         name.trim_end_matches(&self.domain)
             .trim_end_matches('.')
             .into()
     }
 
-    fn log_missing_domains(&self, remote_domains: &[PorkbunRecord]) -> usize {
-        let actual = remote_domains
+    fn remote_names(&self, remote_domains: &[PorkbunRecord]) -> HashSet<String> {
+        remote_domains
             .iter()
             .map(|x| self.strip_domain_from_name(&x.name))
-            .collect::<HashSet<String>>();
-        crate::core::log_missing_domains(&self.records, &actual, "Porkbun", &self.domain)
+            .collect::<HashSet<String>>()
+    }
+
+    fn log_missing_domains(&self, remote_domains: &[PorkbunRecord]) -> usize {
+        let actual = self.remote_names(remote_domains);
+        let missing =
+            crate::core::log_missing_domains(&self.records, &actual, "Porkbun", &self.domain);
+        for name in &missing {
+            self.changes.missing(&crate::core::fqdn(name, &self.domain));
+        }
+        missing.len()
+    }
+
+    fn missing_domains(&self, remote_domains: &[PorkbunRecord]) -> Vec<String> {
+        crate::core::missing_records(&self.records, &self.remote_names(remote_domains))
     }
 
     async fn fetch_records(&self, ip_type: IpType) -> Result<Vec<PorkbunRecord>, DnessError> {
@@ -79,7 +124,7 @@ impl PorkbunClient<'_> {
                 apikey: self.key.clone(),
                 secretapikey: self.secret.clone(),
             })
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&post_url, "porkbun fetch records", e))?
             .error_for_status()
@@ -107,7 +152,7 @@ impl PorkbunClient<'_> {
                 ttl: record.ttl.clone(),
                 r#type: record.r#type.clone(),
             })
-            .send()
+            .send_retry(self.retry)
             .await
             .map_err(|e| DnessError::send_http(&post_url, "porkbun update records", e))?
             .error_for_status()
@@ -116,6 +161,54 @@ impl PorkbunClient<'_> {
         Ok(())
     }
 
+    async fn create_record(&self, name: &str, addr: IpAddr, ttl: u32) -> Result<(), DnessError> {
+        let post_url = format!("{}/dns/create/{}", self.base_url, self.domain);
+
+        self.client
+            .post(&post_url)
+            .json(&PorkbunRecordsEditRequest {
+                apikey: self.key.clone(),
+                secretapikey: self.secret.clone(),
+                name: name.to_string(),
+                content: addr.to_string(),
+                ttl: ttl.to_string(),
+                r#type: String::from(IpType::from(addr).record_type()),
+            })
+            .send_retry_non_idempotent(self.retry)
+            .await
+            .map_err(|e| DnessError::send_http(&post_url, "porkbun create records", e))?
+            .error_for_status()
+            .map_err(|e| DnessError::bad_response(&post_url, "porkbun create records", e))?;
+
+        Ok(())
+    }
+
+    /// Creates the configured records that don't exist yet
+    async fn create_missing(
+        &self,
+        remote_domains: &[PorkbunRecord],
+        addr: IpAddr,
+        ttl: u32,
+    ) -> Result<Updates, DnessError> {
+        let missing = self.missing_domains(remote_domains);
+        for name in &missing {
+            if self.dry_run {
+                let fqdn = crate::core::fqdn(name, &self.domain);
+                self.changes.plan(&fqdn, &self.domain, "", addr);
+            } else {
+                self.create_record(name, addr, ttl).await?;
+                let fqdn = crate::core::fqdn(name, &self.domain);
+                self.changes.push(&fqdn, &self.domain, "", addr);
+                info!("{} from domain {} created with {}", name, self.domain, addr)
+            }
+        }
+
+        Ok(Updates {
+            updated: missing.len() as i32,
+            ..Updates::default()
+        })
+    }
+
     async fn ensure_current_ip(
         &self,
         record: &PorkbunRecord,
@@ -127,14 +220,22 @@ impl PorkbunClient<'_> {
             Ok(ip) => {
                 if ip != addr {
                     updated += 1;
-                    self.update_record(record, addr).await?;
-
-                    info!(
-                        "{} from domain {} updated from {} to {}",
-                        record.name, self.domain, record.content, addr
-                    )
+                    if self.dry_run {
+                        self.changes
+                            .plan(&record.name, &self.domain, &record.content, addr);
+                    } else {
+                        self.update_record(record, addr).await?;
+                        self.changes
+                            .push(&record.name, &self.domain, &record.content, addr);
+
+                        info!(
+                            "{} from domain {} updated from {} to {}",
+                            record.name, self.domain, record.content, addr
+                        )
+                    }
                 } else {
                     current += 1;
+                    self.changes.current(&record.name);
                     debug!(
                         "{} from domain {} is already current",
                         record.name, self.domain
@@ -144,12 +245,19 @@ impl PorkbunClient<'_> {
             Err(ref e) => {
                 updated += 1;
                 warn!("could not parse domain {} address {} as ipv4 -- will replace it. Original error: {}", record.name, record.content, e);
-                self.update_record(record, addr).await?;
+                if self.dry_run {
+                    self.changes
+                        .plan(&record.name, &self.domain, &record.content, addr);
+                } else {
+                    self.update_record(record, addr).await?;
+                    self.changes
+                        .push(&record.name, &self.domain, &record.content, addr);
 
-                info!(
-                    "{} from domain {} updated from {} to {}",
-                    record.name, self.domain, record.content, addr
-                )
+                    info!(
+                        "{} from domain {} updated from {} to {}",
+                        record.name, self.domain, record.content, addr
+                    )
+                }
             }
         }
 
@@ -161,6 +269,22 @@ impl PorkbunClient<'_> {
     }
 }
 
+/// Confirms that Porkbun accepts the credentials and returns the configured records that don't
+/// exist in the domain
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &PorkbunConfig,
+    ip_type: IpType,
+) -> Result<Vec<String>, DnessError> {
+    let porkbun_client = PorkbunClient::create(ctx, config);
+    let records = porkbun_client.fetch_records(ip_type).await?;
+    Ok(porkbun_client
+        .missing_domains(&records)
+        .into_iter()
+        .map(|x| if x.is_empty() { String::from("@") } else { x })
+        .collect())
+}
+
 /// Porkbun dynamic dns service works as the following:
 ///
 /// 1. Send a GET request to find all records in the domain
@@ -169,36 +293,20 @@ impl PorkbunClient<'_> {
 /// 4. Update the remote IP as needed, ensuring that original properties are preserved in the
 ///    upload, so that we don't overwrite a property like TTL.
 pub async fn update_domains(
-    client: &reqwest::Client,
+    ctx: &Context<'_>,
     config: &PorkbunConfig,
     addr: IpAddr,
 ) -> Result<Updates, DnessError> {
-    let porkbun_client = PorkbunClient {
-        base_url: config.base_url.trim_end_matches('/').to_string(),
-        domain: config.domain.clone(),
-        key: config.key.clone(),
-        secret: config.secret.clone(),
-        records: config
-            .records
-            .iter()
-            .map(|r| {
-                // To be consistent with other dns providers we allow the user to use '@' for root
-                // domain. Porkbun uses an empty string, so we map that here.
-                if r == "@" {
-                    String::from("")
-                } else {
-                    r.to_string()
-                }
-            })
-            .collect(),
-        client,
-    };
+    let porkbun_client = PorkbunClient::create(ctx, config);
 
     let records = porkbun_client.fetch_records(addr.into()).await?;
-    let missing = porkbun_client.log_missing_domains(&records) as i32;
-    let mut summary = Updates {
-        missing,
-        ..Updates::default()
+    let mut summary = if let Some(ttl) = porkbun_client.create_ttl {
+        porkbun_client.create_missing(&records, addr, ttl).await?
+    } else {
+        Updates {
+            missing: porkbun_client.log_missing_domains(&records) as i32,
+            ..Updates::default()
+        }
     };
 
     for record in records {
@@ -216,7 +324,10 @@ pub async fn update_domains(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DnsTransport;
     use crate::config::IpType;
+    use crate::dns::DnsResolver;
+    use std::collections::HashMap;
     use std::net::Ipv4Addr;
 
     #[test]
@@ -281,6 +392,9 @@ mod tests {
                 "/api/json/v3/dns/edit/example.com/354399918" => {
                     Response::from_data("application/json", r#"{"status": "SUCCESS"}"#)
                 }
+                "/api/json/v3/dns/create/example.com" => {
+                    Response::from_data("application/json", r#"{"status": "SUCCESS", "id": 1}"#)
+                }
                 _ => Response::empty_404(),
             })
             .unwrap();
@@ -301,6 +415,10 @@ mod tests {
     async fn test_porkbun_update() {
         let (tx, addr) = porkbun_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 1));
         let config = PorkbunConfig {
             base_url: format!("http://{}/api/json/v3", addr),
@@ -309,9 +427,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@"), String::from("sub")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -328,6 +452,10 @@ mod tests {
     async fn test_porkbun_current() {
         let (tx, addr) = porkbun_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = PorkbunConfig {
             base_url: format!("http://{}/api/json/v3", addr),
@@ -336,9 +464,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@"), String::from("sub")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -355,6 +489,10 @@ mod tests {
     async fn test_porkbun_missing() {
         let (tx, addr) = porkbun_rouille_server!();
         let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
         let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
         let config = PorkbunConfig {
             base_url: format!("http://{}/api/json/v3", addr),
@@ -363,9 +501,15 @@ mod tests {
             secret: String::from("secret-1"),
             records: vec![String::from("@"), String::from("sub"), String::from("sub2")],
             ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: false,
+            create_ttl: 600,
         };
 
-        let summary = update_domains(&http_client, &config, new_ip).await.unwrap();
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
         tx.send(()).unwrap();
 
         assert_eq!(
@@ -377,4 +521,41 @@ mod tests {
             }
         )
     }
+
+    #[tokio::test]
+    async fn test_porkbun_create_missing() {
+        let (tx, addr) = porkbun_rouille_server!();
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+        let new_ip = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let config = PorkbunConfig {
+            base_url: format!("http://{}/api/json/v3", addr),
+            domain: String::from("example.com"),
+            key: String::from("key-1"),
+            secret: String::from("secret-1"),
+            records: vec![String::from("@"), String::from("sub"), String::from("sub2")],
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+            create_missing: true,
+            create_ttl: 600,
+        };
+
+        let summary = update_domains(&ctx, &config, new_ip).await.unwrap();
+        tx.send(()).unwrap();
+
+        assert_eq!(
+            summary,
+            Updates {
+                current: 2,
+                updated: 1,
+                missing: 0,
+            }
+        )
+    }
 }
@@ -0,0 +1,117 @@
+use crate::config::DomainConfig;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Combines the first `prefix_length` bits of the address with the rest of the suffix
+pub fn combine(addr: Ipv6Addr, prefix_length: u8, suffix: Ipv6Addr) -> Ipv6Addr {
+    let mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_length.min(128)))
+        .unwrap_or(0);
+    Ipv6Addr::from((u128::from(addr) & mask) | (u128::from(suffix) & !mask))
+}
+
+/// Splits the domain's records by the address they should point at. Records with an IPv6
+/// suffix point at the resolved address's prefix combined with the suffix, and the rest point at
+/// the resolved address itself.
+pub fn address_groups(domain: &DomainConfig, addr: IpAddr) -> Vec<(Cow<'_, DomainConfig>, IpAddr)> {
+    let (v6, suffixes, prefix_length) = match (addr, domain.ipv6_suffixes()) {
+        (IpAddr::V6(v6), Some((suffixes, prefix_length))) if !suffixes.is_empty() => {
+            (v6, suffixes, prefix_length)
+        }
+        _ => return vec![(Cow::Borrowed(domain), addr)],
+    };
+
+    let mut groups: BTreeMap<IpAddr, Vec<String>> = BTreeMap::new();
+    for record in domain.records() {
+        let record_addr = match suffixes.get(&record) {
+            Some(suffix) => IpAddr::V6(combine(v6, prefix_length, *suffix)),
+            None => addr,
+        };
+        groups.entry(record_addr).or_default().push(record);
+    }
+
+    groups
+        .into_iter()
+        .map(|(addr, records)| (Cow::Owned(domain.with_records(records)), addr))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GoDaddyConfig;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    fn godaddy_domain(suffixes: &[(&str, &str)], prefix_length: u8) -> DomainConfig {
+        DomainConfig::GoDaddy(GoDaddyConfig {
+            suffixes: suffixes
+                .iter()
+                .map(|(record, suffix)| (String::from(*record), suffix.parse().unwrap()))
+                .collect::<HashMap<_, _>>(),
+            prefix_length,
+            ..GoDaddyConfig::for_test(&["@", "nas", "www"])
+        })
+    }
+
+    #[test]
+    fn combine_prefix_and_suffix() {
+        let addr: Ipv6Addr = "2001:db8:aa:bb:1:2:3:4".parse().unwrap();
+        let suffix: Ipv6Addr = "::211:22ff:fe33:4455".parse().unwrap();
+        assert_eq!(
+            combine(addr, 64, suffix),
+            "2001:db8:aa:bb:211:22ff:fe33:4455"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+
+        // With a /56, the subnet id is taken from the suffix
+        let suffix: Ipv6Addr = "::12:0:0:0:1234".parse().unwrap();
+        assert_eq!(
+            combine(addr, 56, suffix),
+            "2001:db8:aa:12::1234".parse::<Ipv6Addr>().unwrap()
+        );
+
+        assert_eq!(combine(addr, 0, suffix), suffix);
+        assert_eq!(combine(addr, 128, suffix), addr);
+    }
+
+    #[test]
+    fn group_records_by_address() {
+        let domain = godaddy_domain(&[("nas", "::1234"), ("www", "::1234")], 64);
+        let addr: IpAddr = "2001:db8:aa:bb::1".parse().unwrap();
+        let groups = address_groups(&domain, addr);
+        let groups: Vec<(Vec<String>, IpAddr)> = groups
+            .into_iter()
+            .map(|(domain, addr)| (domain.records(), addr))
+            .collect();
+
+        assert_eq!(
+            groups,
+            vec![
+                (vec![String::from("@")], addr),
+                (
+                    vec![String::from("nas"), String::from("www")],
+                    "2001:db8:aa:bb::1234".parse().unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_records_untouched() {
+        let domain = godaddy_domain(&[("nas", "::1234")], 64);
+        let v4 = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let groups = address_groups(&domain, v4);
+        assert_eq!(groups.len(), 1);
+        assert!(matches!(groups[0].0, Cow::Borrowed(_)));
+        assert_eq!(groups[0].1, v4);
+
+        let domain = godaddy_domain(&[], 64);
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let groups = address_groups(&domain, v6);
+        assert_eq!(groups.len(), 1);
+        assert!(matches!(groups[0].0, Cow::Borrowed(_)));
+    }
+}
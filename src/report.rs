@@ -0,0 +1,195 @@
+use crate::config::{DomainConfig, IpType};
+use crate::core::{RecordChange, RecordStatus, Updates};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// A machine readable account of an update cycle, printed with `--output json`
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub dry_run: bool,
+    pub success: bool,
+    pub addresses: Vec<AddressReport>,
+    pub domains: Vec<DomainReport>,
+    pub summary: Updates,
+
+    /// Failures that belong to neither an address nor a domain, like saving the state file
+    pub errors: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AddressReport {
+    pub ip_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    /// The error and its causes, outermost first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Vec<String>>,
+}
+
+fn ip_label(ip_type: IpType) -> &'static str {
+    match ip_type {
+        IpType::V4 => "4",
+        IpType::V6 => "6",
+    }
+}
+
+impl AddressReport {
+    pub fn new(ip_type: IpType, result: Result<IpAddr, Vec<String>>) -> AddressReport {
+        let ip_type = ip_label(ip_type);
+        match result {
+            Ok(addr) => AddressReport {
+                ip_type,
+                address: Some(addr),
+                error: None,
+            },
+            Err(chain) => AddressReport {
+                ip_type,
+                address: None,
+                error: Some(chain),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DomainReport {
+    pub name: String,
+    pub provider: &'static str,
+    pub zone: String,
+
+    /// A dual stack domain has an entry for each address type, and with IPv6 prefixes an entry
+    /// for each group of records that share an address
+    pub ip_type: &'static str,
+    pub address: IpAddr,
+
+    /// The records weren't checked with the provider as the state file showed them to be current
+    pub from_state_file: bool,
+    pub summary: Updates,
+    pub records: Vec<RecordReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct RecordReport {
+    pub record: String,
+    #[serde(rename = "type")]
+    pub record_type: &'static str,
+    pub status: RecordStatus,
+
+    /// The previous address, which is absent when the record was created or is missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<IpAddr>,
+}
+
+impl RecordReport {
+    /// Combines the records that changed with those that were left as they were
+    pub fn collect(
+        addr: IpAddr,
+        changes: &[RecordChange],
+        unchanged: &[(String, RecordStatus)],
+    ) -> Vec<RecordReport> {
+        let record_type = IpType::from(addr).record_type();
+        let changed = changes.iter().map(|change| RecordReport {
+            record: change.record.clone(),
+            record_type,
+            status: RecordStatus::Updated,
+            old: Some(change.old.clone()).filter(|old| !old.is_empty()),
+            new: Some(change.new),
+        });
+
+        let unchanged = unchanged.iter().map(|(record, status)| {
+            let current = matches!(status, RecordStatus::Current | RecordStatus::Corrected);
+            RecordReport {
+                record: record.clone(),
+                record_type,
+                status: *status,
+                old: Some(addr.to_string()).filter(|_| current),
+                new: Some(addr).filter(|_| current),
+            }
+        });
+
+        changed.chain(unchanged).collect()
+    }
+}
+
+impl DomainReport {
+    pub fn new(domain: &DomainConfig, addr: IpAddr) -> DomainReport {
+        DomainReport {
+            name: domain.display_name(),
+            provider: domain.provider(),
+            zone: String::from(domain.zone()),
+            ip_type: ip_label(IpType::from(addr)),
+            address: addr,
+            from_state_file: false,
+            summary: Updates::default(),
+            records: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn serialize_records() {
+        let addr = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let changes = vec![
+            RecordChange {
+                record: String::from("a.example.com"),
+                zone: String::from("example.com"),
+                old: String::from("1.1.1.1"),
+                new: addr,
+            },
+            RecordChange {
+                record: String::from("b.example.com"),
+                zone: String::from("example.com"),
+                old: String::new(),
+                new: addr,
+            },
+        ];
+        let unchanged = vec![
+            (String::from("c.example.com"), RecordStatus::Current),
+            (String::from("d.example.com"), RecordStatus::Missing),
+        ];
+
+        let records = RecordReport::collect(addr, &changes, &unchanged);
+        let actual = serde_json::to_value(&records).unwrap();
+        let expected = serde_json::json!([
+            {"record": "a.example.com", "type": "A", "status": "updated", "old": "1.1.1.1", "new": "2.2.2.2"},
+            {"record": "b.example.com", "type": "A", "status": "updated", "new": "2.2.2.2"},
+            {"record": "c.example.com", "type": "A", "status": "current", "old": "2.2.2.2", "new": "2.2.2.2"},
+            {"record": "d.example.com", "type": "A", "status": "missing"},
+        ]);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn serialize_address_error() {
+        let chain = vec![
+            String::from("could not successfully resolve IP"),
+            String::from("dns lookup"),
+        ];
+        let report = AddressReport::new(IpType::V6, Err(chain));
+        let actual = serde_json::to_value(&report).unwrap();
+        let expected = serde_json::json!({
+            "ip_type": "6",
+            "error": ["could not successfully resolve IP", "dns lookup"],
+        });
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn serialize_domain_ip_type() {
+        let domain = DomainConfig::GoDaddy(crate::config::GoDaddyConfig::for_test(&["@"]));
+        let addr = IpAddr::V6("2001:db8::2".parse().unwrap());
+        let actual = serde_json::to_value(DomainReport::new(&domain, addr)).unwrap();
+        assert_eq!(actual["ip_type"], "6");
+        assert_eq!(actual["address"], "2001:db8::2");
+    }
+}
@@ -0,0 +1,310 @@
+use crate::config::{
+    DnsConfig, DnsTransport, InterfaceResolverConfig, IpType, NatPmpResolverConfig,
+    ResolverStrategy, StunResolverConfig, UpnpResolverConfig,
+};
+use crate::dns::{OpenDnsResolver, WhoamiResolver, WhoamiService};
+use crate::errors::DnessError;
+use crate::gateway;
+use crate::http_client;
+use crate::http_resolver::HttpResolver;
+use crate::interface;
+use crate::stun;
+use log::{debug, warn};
+use std::net::IpAddr;
+
+/// A single method of resolving the WAN IP. It is created once so that the underlying resolvers
+/// are reused across update cycles
+enum IpResolver {
+    OpenDns {
+        v4: Box<OpenDnsResolver>,
+        v6: Box<OpenDnsResolver>,
+    },
+    Whoami(Box<WhoamiResolver>),
+    Http(Box<HttpResolver>),
+    Interface(InterfaceResolverConfig),
+    Stun(StunResolverConfig),
+    Upnp {
+        config: UpnpResolverConfig,
+        client: reqwest::Client,
+    },
+    NatPmp(NatPmpResolverConfig),
+}
+
+impl IpResolver {
+    async fn create(name: &str, config: &DnsConfig) -> Result<Self, DnessError> {
+        match name.to_ascii_lowercase().as_str() {
+            "opendns" => Ok(IpResolver::OpenDns {
+                v4: Box::new(
+                    OpenDnsResolver::create(IpType::V4, config.resolvers.transport)
+                        .await?
+                        .with_retry(&config.retry),
+                ),
+                v6: Box::new(
+                    OpenDnsResolver::create(IpType::V6, config.resolvers.transport)
+                        .await?
+                        .with_retry(&config.retry),
+                ),
+            }),
+            "cloudflare-dns" => Self::whoami(WhoamiService::Cloudflare, config).await,
+            "google-dns" => Self::whoami(WhoamiService::Google, config).await,
+            "akamai-dns" => Self::whoami(WhoamiService::Akamai, config).await,
+            "ipify" => Ok(IpResolver::Http(Box::new(
+                HttpResolver::ipify().with_retry(&config.retry),
+            ))),
+            "http" => match &config.resolvers.http {
+                Some(http) => Ok(IpResolver::Http(Box::new(
+                    HttpResolver::create(http.clone())?.with_retry(&config.retry),
+                ))),
+                None => Err(DnessError::message(String::from(
+                    "the http ip resolver requires a [resolvers.http] section",
+                ))),
+            },
+            "interface" => match &config.resolvers.interface {
+                Some(interface) => Ok(IpResolver::Interface(interface.clone())),
+                None => Err(DnessError::message(String::from(
+                    "the interface ip resolver requires a [resolvers.interface] section",
+                ))),
+            },
+            // STUN servers default to public ones, so the section is optional
+            "stun" => Ok(IpResolver::Stun(
+                config.resolvers.stun.clone().unwrap_or_default(),
+            )),
+            "upnp" => Ok(IpResolver::Upnp {
+                config: config.resolvers.upnp.clone().unwrap_or_default(),
+                client: http_client::create_local(&config.http)?,
+            }),
+            "natpmp" => Ok(IpResolver::NatPmp(
+                config.resolvers.natpmp.clone().unwrap_or_default(),
+            )),
+            _ => Err(DnessError::message(format!(
+                "unrecognized ip resolver: {}",
+                name
+            ))),
+        }
+    }
+
+    async fn whoami(service: WhoamiService, config: &DnsConfig) -> Result<Self, DnessError> {
+        let transport = config.resolvers.transport;
+        if service.tls_name().is_none() && transport != DnsTransport::Udp {
+            return Err(DnessError::message(String::from(
+                "google-dns and akamai-dns query authoritative name servers, which only support the udp transport",
+            )));
+        }
+
+        let resolver = WhoamiResolver::create(service, transport)
+            .await?
+            .with_retry(&config.retry);
+        Ok(IpResolver::Whoami(Box::new(resolver)))
+    }
+
+    async fn resolve(
+        &self,
+        client: &reqwest::Client,
+        ip_type: IpType,
+    ) -> Result<IpAddr, DnessError> {
+        match self {
+            IpResolver::OpenDns { v4, v6 } => {
+                let opendns = match ip_type {
+                    IpType::V4 => v4,
+                    IpType::V6 => v6,
+                };
+                opendns.wan_lookup().await.map_err(|x| x.into())
+            }
+            IpResolver::Whoami(whoami) => whoami.wan_lookup(ip_type).await.map_err(|x| x.into()),
+            IpResolver::Http(http) => http.resolve(client, ip_type).await,
+            IpResolver::Interface(config) => {
+                interface::interface_addr(config, ip_type).map_err(|x| x.into())
+            }
+            IpResolver::Stun(config) => {
+                stun::stun_addr(config, ip_type).await.map_err(|x| x.into())
+            }
+            IpResolver::Upnp { config, client } => {
+                gateway::upnp_addr(client, config, ip_type).await
+            }
+            IpResolver::NatPmp(config) => gateway::natpmp_addr(config, ip_type).await,
+        }
+    }
+}
+
+/// The configured resolvers and how their answers are combined into the WAN IP
+pub struct IpResolvers {
+    resolvers: Vec<(String, IpResolver)>,
+    strategy: ResolverStrategy,
+    quorum: usize,
+}
+
+impl IpResolvers {
+    pub async fn create(config: &DnsConfig) -> Result<Self, DnessError> {
+        if config.ip_resolver.is_empty() {
+            return Err(DnessError::message(String::from(
+                "at least one ip resolver must be configured",
+            )));
+        }
+
+        let mut resolvers = Vec::new();
+        for name in &config.ip_resolver {
+            resolvers.push((name.clone(), IpResolver::create(name, config).await?));
+        }
+
+        // Default to a simple majority
+        let quorum = config.resolvers.quorum.unwrap_or(resolvers.len() / 2 + 1);
+
+        if config.resolvers.strategy == ResolverStrategy::Quorum
+            && (quorum == 0 || quorum > resolvers.len())
+        {
+            return Err(DnessError::message(format!(
+                "a quorum of {} can't be reached with {} ip resolver(s)",
+                quorum,
+                resolvers.len()
+            )));
+        }
+
+        Ok(IpResolvers {
+            resolvers,
+            strategy: config.resolvers.strategy,
+            quorum,
+        })
+    }
+
+    pub async fn resolve(
+        &self,
+        client: &reqwest::Client,
+        ip_type: IpType,
+    ) -> Result<IpAddr, DnessError> {
+        match self.strategy {
+            ResolverStrategy::FirstSuccess => self.first_success(client, ip_type).await,
+            ResolverStrategy::Quorum => self.quorum(client, ip_type).await,
+        }
+    }
+
+    /// Tries each resolver in order, returning the first answer
+    async fn first_success(
+        &self,
+        client: &reqwest::Client,
+        ip_type: IpType,
+    ) -> Result<IpAddr, DnessError> {
+        let mut last_err = None;
+        for (name, resolver) in &self.resolvers {
+            match resolver.resolve(client, ip_type).await {
+                Ok(addr) => {
+                    debug!("{} resolved address to {}", name, addr);
+                    return Ok(addr);
+                }
+                Err(e) => {
+                    if self.resolvers.len() > 1 {
+                        warn!("{} could not resolve address, trying the next: {}", name, e);
+                    }
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        Err(last_err.expect("at least one resolver to be configured"))
+    }
+
+    /// Asks every resolver at once and accepts the address that enough of them agree on
+    async fn quorum(
+        &self,
+        client: &reqwest::Client,
+        ip_type: IpType,
+    ) -> Result<IpAddr, DnessError> {
+        let answers = futures::future::join_all(
+            self.resolvers
+                .iter()
+                .map(|(_, resolver)| resolver.resolve(client, ip_type)),
+        )
+        .await;
+
+        let mut answered = Vec::new();
+        for ((name, _), answer) in self.resolvers.iter().zip(answers) {
+            match answer {
+                Ok(addr) => {
+                    debug!("{} resolved address to {}", name, addr);
+                    answered.push((name.as_str(), addr));
+                }
+                Err(e) => warn!("{} could not resolve address: {}", name, e),
+            }
+        }
+
+        let agreed = tally(&answered, self.quorum);
+        if let [addr] = agreed.as_slice() {
+            return Ok(*addr);
+        }
+
+        // With a quorum of no more than half of the resolvers, different addresses can each reach
+        // it, and picking one of them would be arbitrary
+        let answers = answered
+            .iter()
+            .map(|(name, addr)| format!("{}: {}", name, addr))
+            .collect::<Vec<String>>();
+        let problem = if agreed.is_empty() {
+            "did not reach"
+        } else {
+            "reached more than one address with"
+        };
+        Err(DnessError::message(format!(
+            "ip resolvers {} a quorum of {} (answers: {})",
+            problem,
+            self.quorum,
+            if answers.is_empty() {
+                String::from("none")
+            } else {
+                answers.join(", ")
+            }
+        )))
+    }
+}
+
+/// The distinct addresses given by at least `quorum` of the answers, in the order first given
+fn tally(answers: &[(&str, IpAddr)], quorum: usize) -> Vec<IpAddr> {
+    let mut agreed = Vec::new();
+    for (_, addr) in answers {
+        let count = answers.iter().filter(|(_, x)| x == addr).count();
+        if count >= quorum && !agreed.contains(addr) {
+            agreed.push(*addr);
+        }
+    }
+    agreed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn tally_quorum() {
+        let a = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let b = IpAddr::V4(Ipv4Addr::new(6, 6, 6, 6));
+        let answers = [("opendns", b), ("ipify", a), ("http", a)];
+
+        // Both addresses reach a quorum of one, which is ambiguous
+        assert_eq!(tally(&answers, 1), vec![b, a]);
+        assert_eq!(tally(&answers, 2), vec![a]);
+        assert!(tally(&answers, 3).is_empty());
+        assert!(tally(&[], 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn create_rejects_unreachable_quorum() {
+        let mut config: DnsConfig = toml::from_str("ip_resolver = [\"ipify\", \"ipify\"]").unwrap();
+        config.resolvers.strategy = ResolverStrategy::Quorum;
+
+        config.resolvers.quorum = Some(3);
+        assert!(IpResolvers::create(&config).await.is_err());
+
+        config.resolvers.quorum = None;
+        let resolvers = IpResolvers::create(&config).await.unwrap();
+        assert_eq!(resolvers.quorum, 2);
+    }
+
+    #[tokio::test]
+    async fn create_rejects_encrypted_authoritative_whoami() {
+        let mut config: DnsConfig = toml::from_str("ip_resolver = \"google-dns\"").unwrap();
+        config.resolvers.transport = DnsTransport::Https;
+        assert!(IpResolvers::create(&config).await.is_err());
+
+        config.ip_resolver = vec![String::from("cloudflare-dns")];
+        assert!(IpResolvers::create(&config).await.is_ok());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::config::RetryConfig;
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// A number that is different on each call. Each `RandomState` is seeded differently, which is
+/// random enough to spread out retries and pick message ids, but not for anything secret.
+pub fn random() -> u64 {
+    RandomState::new().hash_one(0u8)
+}
+
+/// A number in [0, 1) that is different on each call
+fn jitter() -> f64 {
+    let bits = random() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+fn seconds(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or_default()
+}
+
+impl RetryConfig {
+    /// The delay before the given retry, counting from 1. The delay doubles with each retry, up to
+    /// the max, and then a random amount of up to half of it is taken off so that clients that
+    /// failed together don't retry together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(30) as i32;
+        let delay = (self.base_delay * 2f64.powi(exponent)).min(self.max_delay);
+        seconds(delay / 2.0 + delay / 2.0 * jitter())
+    }
+
+    fn retries_status(&self, response: &Response) -> bool {
+        self.statuses.contains(&response.status().as_u16())
+    }
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    parse_retry_after(response.headers().get(RETRY_AFTER)?.to_str().ok()?)
+}
+
+/// The delay asked for by a Retry-After header, given as either seconds or a date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Errors where the request likely never reached the server, so it's safe to send it again
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout()
+}
+
+pub trait SendRetry {
+    /// Sends the request, retrying connection errors, timeouts, and responses with one of the
+    /// configured statuses. The last response is returned even when its status would have been
+    /// retried, so that the caller reports it as usual.
+    async fn send_retry(self, retry: &RetryConfig) -> reqwest::Result<Response>;
+
+    /// Sends a request that must not be applied twice, like creating a record. It's only retried
+    /// when the connection couldn't be made, as after a timeout or an error status the server may
+    /// have applied it already.
+    async fn send_retry_non_idempotent(self, retry: &RetryConfig) -> reqwest::Result<Response>;
+}
+
+impl SendRetry for RequestBuilder {
+    async fn send_retry(self, retry: &RetryConfig) -> reqwest::Result<Response> {
+        send(self, retry, true).await
+    }
+
+    async fn send_retry_non_idempotent(self, retry: &RetryConfig) -> reqwest::Result<Response> {
+        send(self, retry, false).await
+    }
+}
+
+/// Sends the request, where a request that isn't idempotent is only sent again when it never
+/// reached the server
+async fn send(
+    builder: RequestBuilder,
+    retry: &RetryConfig,
+    idempotent: bool,
+) -> reqwest::Result<Response> {
+    let mut attempt = 1;
+    loop {
+        // Requests with a streamed body can't be cloned, so they are only sent once
+        let request = match builder.try_clone() {
+            Some(request) if attempt < retry.attempts => request,
+            _ => return builder.send().await,
+        };
+
+        let delay = match request.send().await {
+            Ok(response) if idempotent && retry.retries_status(&response) => {
+                let delay = match retry_after(&response) {
+                    Some(delay) => delay.min(seconds(retry.max_delay)),
+                    None => retry.backoff(attempt),
+                };
+                warn!(
+                    "{} responded with {}, retrying in {:.1}s (attempt {} of {})",
+                    response.url(),
+                    response.status(),
+                    delay.as_secs_f64(),
+                    attempt,
+                    retry.attempts
+                );
+                delay
+            }
+            Err(e) if is_transient(&e) && (idempotent || e.is_connect()) => {
+                let delay = retry.backoff(attempt);
+                warn!(
+                    "{}, retrying in {:.1}s (attempt {} of {})",
+                    e,
+                    delay.as_secs_f64(),
+                    attempt,
+                    retry.attempts
+                );
+                delay
+            }
+            result => return result,
+        };
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Runs the operation until it succeeds, fails with an error that isn't transient, or runs out of
+/// attempts
+pub async fn retry<T, E, F, Fut>(
+    retry: &RetryConfig,
+    what: &str,
+    transient: fn(&E) -> bool,
+    mut op: F,
+) -> Result<T, E>
+where
+    E: fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if attempt < retry.attempts && transient(&e) => {
+                let delay = retry.backoff(attempt);
+                warn!(
+                    "{} failed, retrying in {:.1}s (attempt {} of {}): {}",
+                    what,
+                    delay.as_secs_f64(),
+                    attempt,
+                    retry.attempts,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn no_delay() -> RetryConfig {
+        RetryConfig {
+            base_delay: 0.0,
+            max_delay: 0.0,
+            ..RetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let retry = RetryConfig {
+            base_delay: 1.0,
+            max_delay: 5.0,
+            ..RetryConfig::default()
+        };
+
+        for (attempt, full) in [(1, 1.0), (2, 2.0), (3, 4.0), (4, 5.0), (40, 5.0)] {
+            let delay = retry.backoff(attempt).as_secs_f64();
+            assert!(
+                delay >= full / 2.0 && delay <= full,
+                "{} {}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_retries_statuses() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let server_hits = Arc::clone(&hits);
+        let server = rouille::Server::new("localhost:0", move |_request| {
+            match server_hits.fetch_add(1, Ordering::SeqCst) {
+                0 => rouille::Response::text("bad gateway").with_status_code(502),
+                1 => rouille::Response::text("slow down")
+                    .with_status_code(429)
+                    .with_additional_header("Retry-After", "0"),
+                _ => rouille::Response::text("ok"),
+            }
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, sender) = server.stoppable();
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/", addr);
+        let response = client.get(&url).send_retry(&no_delay()).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // Out of attempts, the last response is handed back
+        hits.store(0, Ordering::SeqCst);
+        let retry = RetryConfig {
+            attempts: 1,
+            ..no_delay()
+        };
+        let response = client.get(&url).send_retry(&retry).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        sender.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_not_resent() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let server_hits = Arc::clone(&hits);
+        let server = rouille::Server::new("localhost:0", move |request| {
+            server_hits.fetch_add(1, Ordering::SeqCst);
+            if request.url() == "/slow" {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+            rouille::Response::text("bad gateway").with_status_code(502)
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, sender) = server.stoppable();
+        let client = reqwest::Client::new();
+
+        // The server may have created the record before failing
+        let url = format!("http://{}/", addr);
+        let response = client
+            .post(&url)
+            .send_retry_non_idempotent(&no_delay())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // Nor is a request that timed out waiting on the response
+        hits.store(0, Ordering::SeqCst);
+        let url = format!("http://{}/slow", addr);
+        let err = client
+            .post(&url)
+            .timeout(Duration::from_millis(100))
+            .send_retry_non_idempotent(&no_delay())
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        sender.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn retry_stops_on_permanent_errors() {
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), String> = retry(
+            &no_delay(),
+            "lookup",
+            |e: &String| e == "timeout",
+            || {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call {
+                        0 => Err(String::from("timeout")),
+                        _ => Err(String::from("not found")),
+                    }
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Err(String::from("not found")));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn parse_retry_after_values() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
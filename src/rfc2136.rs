@@ -0,0 +1,507 @@
+use crate::authoritative;
+use crate::config::{IpType, RetryConfig, Rfc2136Config, TsigAlgorithm};
+use crate::core::{fqdn, Context, Updates};
+use crate::errors::DnessError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hickory_resolver::proto::dnssec::rdata::tsig::TsigAlgorithm as HmacAlgorithm;
+use hickory_resolver::proto::dnssec::tsig::TSigner;
+use hickory_resolver::proto::op::{
+    Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage,
+};
+use hickory_resolver::proto::rr::rdata::{A, AAAA};
+use hickory_resolver::proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use log::info;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// How long to wait for the server to answer a message
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many seconds the clocks of dness and the server may be apart, as recommended by RFC 8945
+const FUDGE: u16 = 300;
+
+/// Decodes the base64 key secret
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    STANDARD.decode(secret.trim())
+}
+
+/// Adds the standard dns port to the server when it isn't given one
+fn server_address(server: &str) -> String {
+    match server.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, 53).to_string(),
+        Err(_) if server.contains(':') => String::from(server),
+        Err(_) => format!("{}:53", server),
+    }
+}
+
+fn record_type(ip_type: IpType) -> RecordType {
+    match ip_type {
+        IpType::V4 => RecordType::A,
+        IpType::V6 => RecordType::AAAA,
+    }
+}
+
+/// A new message with a random id. The id only pairs a response with its request, as the TSIG
+/// signature is what authenticates the response.
+fn new_message(op_code: OpCode) -> Message {
+    let mut message = Message::new();
+    message
+        .set_id(crate::retry::random() as u16)
+        .set_message_type(MessageType::Query)
+        .set_op_code(op_code)
+        .set_recursion_desired(false);
+    message
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no response from the server")
+}
+
+/// Sends the message over tcp, for responses that don't fit in a udp packet
+async fn send_tcp(addr: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(&(request.len() as u16).to_be_bytes())
+            .await?;
+        stream.write_all(request).await?;
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0; usize::from(len)];
+        stream.read_exact(&mut buf).await?;
+        Ok(buf)
+    };
+
+    tokio::time::timeout(EXCHANGE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| timed_out())?
+}
+
+pub struct Rfc2136Client<'a> {
+    config: &'a Rfc2136Config,
+    retry: &'a RetryConfig,
+    server: String,
+    zone: Name,
+    signer: TSigner,
+}
+
+impl<'a> Rfc2136Client<'a> {
+    pub fn create(config: &'a Rfc2136Config, retry: &'a RetryConfig) -> Result<Self, DnessError> {
+        let key = decode_secret(&config.key_secret).map_err(|e| {
+            DnessError::message(format!(
+                "key_secret of {} is not valid base64: {}",
+                config.key_name, e
+            ))
+        })?;
+        let algorithm = match config.key_algorithm {
+            TsigAlgorithm::HmacSha256 => HmacAlgorithm::HmacSha256,
+            TsigAlgorithm::HmacSha512 => HmacAlgorithm::HmacSha512,
+        };
+        let signer = TSigner::new(key, algorithm, parse_name(&config.key_name)?, FUDGE)
+            .map_err(|e| DnessError::message(format!("invalid tsig key: {}", e)))?;
+
+        Ok(Rfc2136Client {
+            config,
+            retry,
+            server: server_address(&config.server),
+            zone: parse_name(&config.zone)?,
+            signer,
+        })
+    }
+
+    fn error(&self, context: &str, reason: String) -> DnessError {
+        DnessError::dns_update(&self.server, context, reason)
+    }
+
+    /// Asks the primary server for the addresses (and their ttl) that the host has
+    async fn current_records(
+        &self,
+        host: &Name,
+        ip_type: IpType,
+    ) -> Result<Vec<(IpAddr, u32)>, DnessError> {
+        let mut message = new_message(OpCode::Query);
+        message.add_query(Query::query(host.clone(), record_type(ip_type)));
+        let response = self.exchange(message, "dns query").await?;
+
+        let records = response
+            .answers()
+            .iter()
+            .filter_map(|x| match x.data() {
+                RData::A(ip) => Some((IpAddr::V4(ip.0), x.ttl())),
+                RData::AAAA(ip) => Some((IpAddr::V6(ip.0), x.ttl())),
+                _ => None,
+            })
+            .collect();
+        Ok(records)
+    }
+
+    /// Replaces the address records of every host with the one address, in a single update so
+    /// that the zone changes all at once
+    async fn replace(&self, hosts: &[Name], wan: IpAddr) -> Result<(), DnessError> {
+        let mut zone = Query::new();
+        zone.set_name(self.zone.clone())
+            .set_query_class(DNSClass::IN)
+            .set_query_type(RecordType::SOA);
+
+        let mut message = new_message(OpCode::Update);
+        message.add_zone(zone);
+
+        let rdata = match wan {
+            IpAddr::V4(ip) => RData::A(A(ip)),
+            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+        };
+        for host in hosts {
+            // RFC 2136 2.5.2: delete the RRset, then 2.5.1: add the new record to it
+            let mut delete = Record::update0(host.clone(), 0, rdata.record_type());
+            delete.set_dns_class(DNSClass::ANY);
+            message.add_update(delete);
+
+            let add = Record::from_rdata(host.clone(), self.config.ttl, rdata.clone());
+            message.add_update(add);
+        }
+
+        self.exchange(message, "dns update").await.map(|_| ())
+    }
+
+    /// Signs the message, sends it to the server, and returns the authenticated response
+    async fn exchange(&self, mut message: Message, context: &str) -> Result<Message, DnessError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut verifier = message
+            .finalize(&self.signer, now as u32)
+            .map_err(|e| self.error(context, format!("could not sign the message: {}", e)))?;
+        let request = message
+            .to_vec()
+            .map_err(|e| self.error(context, format!("could not encode the message: {}", e)))?;
+
+        // Every io error is retried, even when the server may have applied the message before the
+        // response was lost. That is harmless only because each update deletes the records before
+        // adding them, so applying it twice leaves the same records as applying it once.
+        let transient = |_: &io::Error| true;
+        let bytes = crate::retry::retry(self.retry, context, transient, || self.send(&request))
+            .await
+            .map_err(|e| self.error(context, e.to_string()))?;
+
+        let response = Message::from_vec(&bytes)
+            .map_err(|e| self.error(context, format!("could not decode the response: {}", e)))?;
+        if response.id() != message.id() {
+            return Err(self.error(context, String::from("response is for another message")));
+        }
+
+        // The response code can't be trusted until the signature is checked. Errors about the key
+        // itself come back unsigned, so the code they claim is only added to the message.
+        if let Some(verify) = verifier.as_mut() {
+            verify(&bytes).map_err(|e| {
+                let reason = match response.response_code() {
+                    ResponseCode::NoError => format!("could not authenticate the response: {}", e),
+                    code => format!(
+                        "could not authenticate the response, which claimed {}: {}",
+                        code, e
+                    ),
+                };
+                self.error(context, reason)
+            })?;
+        }
+
+        match response.response_code() {
+            ResponseCode::NoError | ResponseCode::NXDomain => {}
+            code => {
+                return Err(self.error(context, format!("server responded with: {}", code)));
+            }
+        }
+
+        Ok(response)
+    }
+
+    async fn send(&self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let addr = tokio::net::lookup_host(&self.server)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "server has no address"))?;
+        let local = match addr {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        socket.send(request).await?;
+
+        let mut buf = vec![0; 4096];
+        let len = tokio::time::timeout(EXCHANGE_TIMEOUT, socket.recv(&mut buf))
+            .await
+            .map_err(|_| timed_out())??;
+        buf.truncate(len);
+
+        let truncated = Message::from_vec(&buf).is_ok_and(|x| x.truncated());
+        if truncated {
+            send_tcp(addr, request).await
+        } else {
+            Ok(buf)
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Result<Name, DnessError> {
+    let mut parsed = Name::from_ascii(name)
+        .map_err(|e| DnessError::message(format!("invalid name {}: {}", name, e)))?;
+    parsed.set_fqdn(true);
+    Ok(parsed)
+}
+
+/// Returns the configured records that don't have an address on the primary server. The queries
+/// are signed, so a bad key is caught here too.
+pub async fn missing_records(
+    ctx: &Context<'_>,
+    config: &Rfc2136Config,
+    ip_type: IpType,
+) -> Result<Vec<String>, DnessError> {
+    let client = Rfc2136Client::create(config, ctx.retry)?;
+    let mut missing = Vec::new();
+    for record in &config.records {
+        let host = parse_name(&fqdn(record, &config.zone))?;
+        if client.current_records(&host, ip_type).await?.is_empty() {
+            missing.push(record.clone());
+        }
+    }
+    Ok(missing)
+}
+
+pub async fn update_domains(
+    ctx: &Context<'_>,
+    config: &Rfc2136Config,
+    wan: IpAddr,
+) -> Result<Updates, DnessError> {
+    // The current records are asked of the primary server, as it's the one being updated and
+    // there's no need to wait on caches or secondaries
+    let client = Rfc2136Client::create(config, ctx.retry)?;
+    let mut results = Updates::default();
+    let mut stale = Vec::new();
+
+    for record in &config.records {
+        let host = fqdn(record, &config.zone);
+        let name = parse_name(&host)?;
+        let current = client.current_records(&name, wan.into()).await?;
+        if current == [(wan, config.ttl)] {
+            results.current += 1;
+            ctx.changes.current(&host);
+            continue;
+        }
+
+        let old: Vec<String> = current.iter().map(|(ip, _)| ip.to_string()).collect();
+        let old = old.join(", ");
+        if ctx.dry_run {
+            ctx.changes.plan(&host, &config.zone, &old, wan);
+            results.updated += 1;
+        } else {
+            stale.push((host, name, old));
+        }
+    }
+
+    if !stale.is_empty() {
+        let names: Vec<Name> = stale.iter().map(|(_, name, _)| name.clone()).collect();
+        client.replace(&names, wan).await?;
+        for (host, _, old) in &stale {
+            ctx.changes.push(host, &config.zone, old, wan);
+            info!(
+                "{} from zone {} updated from [{}] to {} with a ttl of {}",
+                host, config.zone, old, wan, config.ttl
+            );
+        }
+        results.updated += stale.len() as i32;
+    }
+
+    let updated: Vec<String> = stale.into_iter().map(|(host, _, _)| host).collect();
+    authoritative::verify(ctx, &updated, wan).await?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DnsTransport;
+    use crate::dns::DnsResolver;
+    use hickory_resolver::proto::dnssec::rdata::tsig::{make_tsig_record, message_tbs, TSIG};
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+
+    const SECRET: &str = "c2VjcmV0LWtleS1mb3ItZG5lc3M=";
+
+    /// A primary server that answers every query with 1.1.1.1, accepts updates signed with
+    /// `SECRET`, and keeps the updates it received
+    async fn mock_server() -> (SocketAddr, Arc<Mutex<Vec<Message>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&updates);
+        let key_name = Name::from_ascii("dness-key.").unwrap();
+        let signer = TSigner::new(
+            decode_secret(SECRET).unwrap(),
+            HmacAlgorithm::HmacSha256,
+            key_name.clone(),
+            FUDGE,
+        )
+        .unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code());
+
+                let request_mac = match signer.verify_message_byte(None, &buf[..len], true) {
+                    Ok((mac, _, _)) => mac,
+                    Err(_) => {
+                        response.set_response_code(ResponseCode::NotAuth);
+                        let bytes = response.to_vec().unwrap();
+                        socket.send_to(&bytes, peer).await.unwrap();
+                        continue;
+                    }
+                };
+
+                if request.op_code() == OpCode::Update {
+                    received.lock().unwrap().push(request);
+                } else {
+                    for query in request.queries() {
+                        response.add_query(query.clone());
+                        if query.query_type() == RecordType::A {
+                            let ip = RData::A(A(Ipv4Addr::new(1, 1, 1, 1)));
+                            response.add_answer(Record::from_rdata(query.name().clone(), 300, ip));
+                        }
+                    }
+                }
+
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                let pre_tsig = TSIG::new(
+                    HmacAlgorithm::HmacSha256,
+                    now.as_secs(),
+                    FUDGE,
+                    Vec::new(),
+                    response.id(),
+                    0,
+                    Vec::new(),
+                );
+                // The request's mac is prepended here rather than by message_tbs, which would
+                // shift the offsets of compressed names away from those on the wire
+                let mut tbs = (request_mac.len() as u16).to_be_bytes().to_vec();
+                tbs.extend(request_mac);
+                tbs.extend(message_tbs(None, &response, &pre_tsig, &key_name).unwrap());
+                let mac = signer.sign(&tbs).unwrap();
+                response.add_tsig(make_tsig_record(key_name.clone(), pre_tsig.set_mac(mac)));
+                let bytes = response.to_vec().unwrap();
+                socket.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+
+        (addr, updates)
+    }
+
+    fn config(addr: SocketAddr, secret: &str) -> Rfc2136Config {
+        Rfc2136Config {
+            server: addr.to_string(),
+            zone: String::from("example.com"),
+            records: vec![String::from("@"), String::from("home")],
+            key_name: String::from("dness-key"),
+            key_secret: String::from(secret),
+            key_algorithm: TsigAlgorithm::HmacSha256,
+            ttl: 300,
+            ip_types: vec![IpType::V4],
+            on_update: None,
+            on_failure: None,
+            suffixes: HashMap::new(),
+            prefix_length: 64,
+        }
+    }
+
+    #[test]
+    fn server_default_port() {
+        assert_eq!(server_address("192.0.2.1"), "192.0.2.1:53");
+        assert_eq!(server_address("2001:db8::1"), "[2001:db8::1]:53");
+        assert_eq!(server_address("ns1.example.com"), "ns1.example.com:53");
+        assert_eq!(
+            server_address("ns1.example.com:5353"),
+            "ns1.example.com:5353"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rfc2136_update() {
+        let (addr, updates) = mock_server().await;
+        let http_client = reqwest::Client::new();
+        let resolver = DnsResolver::create_cloudflare(DnsTransport::Udp)
+            .await
+            .unwrap();
+        let ctx = Context::for_test(&http_client, &resolver);
+
+        let config = config(addr, SECRET);
+        let current = update_domains(&ctx, &config, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)))
+            .await
+            .unwrap();
+        assert_eq!(
+            current,
+            Updates {
+                current: 2,
+                updated: 0,
+                missing: 0,
+            }
+        );
+        assert!(updates.lock().unwrap().is_empty());
+
+        let new_ip = Ipv4Addr::new(2, 2, 2, 2);
+        let summary = update_domains(&ctx, &config, IpAddr::V4(new_ip))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            Updates {
+                current: 0,
+                updated: 2,
+                missing: 0,
+            }
+        );
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        let update = &updates[0];
+        assert_eq!(update.zones()[0].name().to_string(), "example.com.");
+        assert_eq!(update.updates().len(), 4);
+
+        let delete = &update.updates()[2];
+        assert_eq!(delete.name().to_string(), "home.example.com.");
+        assert_eq!(delete.dns_class(), DNSClass::ANY);
+        assert_eq!(delete.record_type(), RecordType::A);
+
+        let add = &update.updates()[3];
+        assert_eq!(add.name().to_string(), "home.example.com.");
+        assert_eq!(add.ttl(), 300);
+        assert_eq!(add.data(), &RData::A(A(new_ip)));
+    }
+
+    #[tokio::test]
+    async fn test_rfc2136_wrong_key() {
+        let (addr, updates) = mock_server().await;
+        let retry = crate::config::RetryConfig::default();
+        let config = config(addr, "d3Jvbmcta2V5");
+        let client = Rfc2136Client::create(&config, &retry).unwrap();
+
+        let name = parse_name("home.example.com").unwrap();
+        let err = client
+            .replace(&[name], IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)))
+            .await;
+        let err = err.unwrap_err().to_string();
+        assert!(err.contains("could not authenticate"), "{}", err);
+        assert!(err.contains("Not authorized"), "{}", err);
+        assert!(updates.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::config::{DomainConfig, IpType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct StateError {
+    kind: StateErrorKind,
+}
+
+#[derive(Debug)]
+pub enum StateErrorKind {
+    Misread(IoError),
+    Parse(serde_json::Error),
+    Write(IoError),
+}
+
+impl error::Error for StateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            StateErrorKind::Misread(ref e) => Some(e),
+            StateErrorKind::Parse(ref e) => Some(e),
+            StateErrorKind::Write(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "state file issue: ")?;
+        match self.kind {
+            StateErrorKind::Misread(ref _e) => write!(f, "unable to read file"),
+            StateErrorKind::Parse(ref _e) => write!(f, "a parsing error"),
+            StateErrorKind::Write(ref _e) => write!(f, "unable to write file"),
+        }
+    }
+}
+
+/// The addresses that were last successfully pushed to each record, so that a subsequent run with
+/// an unchanged WAN IP can skip the provider entirely. Records are keyed by the domain's block in
+/// the config, then the record as written in the config, then the record type (A / AAAA).
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct State {
+    #[serde(default)]
+    domains: BTreeMap<String, BTreeMap<String, BTreeMap<String, IpAddr>>>,
+}
+
+impl State {
+    /// Reads the state from disk. A missing file is not an error as it is expected on first run.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<State, StateError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => {
+                return Err(StateError {
+                    kind: StateErrorKind::Misread(e),
+                })
+            }
+        };
+
+        serde_json::from_str(&contents).map_err(|e| StateError {
+            kind: StateErrorKind::Parse(e),
+        })
+    }
+
+    /// Writes the state to a temporary file before renaming it over the destination so that an
+    /// interrupted write can't leave behind a truncated state file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), StateError> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let data = serde_json::to_vec_pretty(self).map_err(|e| StateError {
+            kind: StateErrorKind::Parse(e),
+        })?;
+
+        fs::write(&tmp, data)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| StateError {
+                kind: StateErrorKind::Write(e),
+            })
+    }
+
+    /// Identifies the domain by its position among the `[[domains]]` as well as its name, as two
+    /// blocks can share a name (like Cloudflare blocks without a zone) yet be different accounts
+    fn key(block: usize, domain: &DomainConfig) -> String {
+        format!("{} #{}", domain.display_name(), block)
+    }
+
+    /// Returns true when every record of the domain, the given block of the config, was last pushed
+    /// with the given address. A domain whose record settings are managed is never current, so
+    /// that drift in those is corrected.
+    pub fn is_current(&self, block: usize, domain: &DomainConfig, addr: IpAddr) -> bool {
+        if domain.manages_record_settings() {
+            return false;
+        }
+
+        let record_type = IpType::from(addr).record_type();
+        let records = domain.records();
+        let cached = match self.domains.get(&Self::key(block, domain)) {
+            Some(cached) => cached,
+            None => return false,
+        };
+
+        !records.is_empty()
+            && records.iter().all(|record| {
+                cached
+                    .get(record)
+                    .and_then(|types| types.get(record_type))
+                    .is_some_and(|x| *x == addr)
+            })
+    }
+
+    /// Remembers that every record of the domain now points at the given address
+    pub fn record(&mut self, block: usize, domain: &DomainConfig, addr: IpAddr) {
+        let record_type = IpType::from(addr).record_type();
+        let cached = self.domains.entry(Self::key(block, domain)).or_default();
+        for record in domain.records() {
+            cached
+                .entry(record)
+                .or_default()
+                .insert(String::from(record_type), addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GoDaddyConfig;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn godaddy_domain(records: &[&str]) -> DomainConfig {
+        DomainConfig::GoDaddy(GoDaddyConfig::for_test(records))
+    }
+
+    #[test]
+    fn state_is_current() {
+        let domain = godaddy_domain(&["@", "a"]);
+        let v4 = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+        let v6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2));
+
+        let mut state = State::default();
+        assert!(!state.is_current(0, &domain, v4));
+
+        state.record(0, &domain, v4);
+        assert!(state.is_current(0, &domain, v4));
+        assert!(!state.is_current(0, &domain, IpAddr::V4(Ipv4Addr::new(2, 2, 2, 1))));
+        assert!(!state.is_current(0, &domain, v6));
+
+        // A newly configured record forces a trip to the provider
+        assert!(!state.is_current(0, &godaddy_domain(&["@", "a", "b"]), v4));
+
+        // Another block with the same name, possibly of another account, has its own state
+        assert!(!state.is_current(1, &domain, v4));
+    }
+
+    #[test]
+    fn state_skips_managed_settings() {
+        let domain: DomainConfig = toml::from_str(
+            r#"
+            type = "cloudflare"
+            token = "dec0de"
+            zone = "example.com"
+            records = ["n.example.com"]
+            "#,
+        )
+        .unwrap();
+        let addr = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+
+        let mut state = State::default();
+        state.record(0, &domain, addr);
+        assert!(state.is_current(0, &domain, addr));
+
+        // The address is cached but the proxied setting may have drifted or changed
+        let DomainConfig::Cloudflare(mut config) = domain else {
+            unreachable!()
+        };
+        config.proxied = Some(true);
+        let domain = DomainConfig::Cloudflare(config);
+        state.record(0, &domain, addr);
+        assert!(!state.is_current(0, &domain, addr));
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let path = std::env::temp_dir().join(format!("dness-state-{}.json", std::process::id()));
+        let domain = godaddy_domain(&["@"]);
+        let addr = IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2));
+
+        assert_eq!(State::load(&path).unwrap(), State::default());
+
+        let mut state = State::default();
+        state.record(0, &domain, addr);
+        state.save(&path).unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.is_current(0, &domain, addr));
+    }
+}
@@ -0,0 +1,338 @@
+use crate::config::{IpType, StunResolverConfig};
+use log::{debug, warn};
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+// UDP is unreliable so requests are retransmitted, waiting a bit longer each time
+const ATTEMPTS: u32 = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug)]
+pub struct StunError {
+    kind: StunErrorKind,
+}
+
+#[derive(Debug)]
+pub enum StunErrorKind {
+    Io(IoError),
+    NoServerAddress {
+        server: String,
+        ip_type: IpType,
+    },
+    Timeout {
+        server: String,
+    },
+    Malformed {
+        server: String,
+        reason: &'static str,
+    },
+    NoServers,
+}
+
+impl error::Error for StunError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            StunErrorKind::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for StunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stun resolver: ")?;
+        match &self.kind {
+            StunErrorKind::Io(_) => write!(f, "network error"),
+            StunErrorKind::NoServerAddress { server, ip_type } => write!(
+                f,
+                "no {} address found for {}",
+                ip_type.record_type(),
+                server
+            ),
+            StunErrorKind::Timeout { server } => write!(f, "no response from {}", server),
+            StunErrorKind::Malformed { server, reason } => {
+                write!(f, "unexpected response from {}: {}", server, reason)
+            }
+            StunErrorKind::NoServers => write!(f, "no stun servers configured"),
+        }
+    }
+}
+
+impl From<IoError> for StunError {
+    fn from(e: IoError) -> Self {
+        StunError {
+            kind: StunErrorKind::Io(e),
+        }
+    }
+}
+
+/// A transaction id that is unpredictable enough to match responses to requests
+fn transaction_id() -> [u8; 12] {
+    let mut id = [0u8; 12];
+    for chunk in id.chunks_mut(8) {
+        let random = crate::retry::random().to_be_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    id
+}
+
+fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction_id);
+    request
+}
+
+/// Reads the mapped address out of a binding response. XOR-MAPPED-ADDRESS is preferred, but
+/// MAPPED-ADDRESS is accepted from servers that predate RFC 5389.
+fn parse_binding_response(
+    response: &[u8],
+    transaction_id: &[u8; 12],
+) -> Result<IpAddr, &'static str> {
+    if response.len() < HEADER_LEN {
+        return Err("response is too short");
+    }
+
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    if message_type != BINDING_SUCCESS {
+        return Err("not a binding success response");
+    }
+
+    if response[4..8] != MAGIC_COOKIE.to_be_bytes() || response[8..20] != transaction_id[..] {
+        return Err("transaction does not match the request");
+    }
+
+    let attributes = response
+        .get(HEADER_LEN..HEADER_LEN + length)
+        .ok_or("response is truncated")?;
+
+    let mut mapped = None;
+    let mut xor_mapped = None;
+    let mut rest = attributes;
+    while rest.len() >= 4 {
+        let attr_type = u16::from_be_bytes([rest[0], rest[1]]);
+        let attr_len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let value = rest.get(4..4 + attr_len).ok_or("attribute is truncated")?;
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = Some(value),
+            ATTR_MAPPED_ADDRESS => mapped = Some(value),
+            _ => {}
+        }
+
+        // Attributes are padded to a multiple of four bytes
+        let padded = (4 + attr_len + 3) & !3;
+        rest = rest.get(padded..).unwrap_or_default();
+    }
+
+    if let Some(value) = xor_mapped {
+        let mut mask = [0u8; 16];
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+        parse_address(value, &mask)
+    } else if let Some(value) = mapped {
+        parse_address(value, &[0u8; 16])
+    } else {
+        Err("no mapped address in response")
+    }
+}
+
+/// Parses an address attribute (a reserved byte, the family, the port, and the address), where
+/// the address is xor'd with the mask
+fn parse_address(value: &[u8], mask: &[u8; 16]) -> Result<IpAddr, &'static str> {
+    let family = *value.get(1).ok_or("address attribute is truncated")?;
+    let address = &value[value.len().min(4)..];
+    let unmask = |bytes: &mut [u8]| {
+        for (byte, mask) in bytes.iter_mut().zip(mask.iter()) {
+            *byte ^= mask;
+        }
+    };
+
+    match (family, address.len()) {
+        (0x01, 4) => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(address);
+            unmask(&mut octets);
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        (0x02, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(address);
+            unmask(&mut octets);
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => Err("unrecognized address family"),
+    }
+}
+
+async fn query_server(server: &str, ip_type: IpType) -> Result<IpAddr, StunError> {
+    let server_addr = tokio::net::lookup_host(server)
+        .await?
+        .find(|addr| IpType::from(addr.ip()) == ip_type)
+        .ok_or_else(|| StunError {
+            kind: StunErrorKind::NoServerAddress {
+                server: String::from(server),
+                ip_type,
+            },
+        })?;
+
+    let bind: SocketAddr = match ip_type {
+        IpType::V4 => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpType::V6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server_addr).await?;
+
+    let transaction_id = transaction_id();
+    let request = binding_request(&transaction_id);
+    let mut buf = [0u8; 576];
+    for attempt in 1..=ATTEMPTS {
+        socket.send(&request).await?;
+        match tokio::time::timeout(ATTEMPT_TIMEOUT * attempt, socket.recv(&mut buf)).await {
+            Ok(received) => {
+                let received = received?;
+                return parse_binding_response(&buf[..received], &transaction_id).map_err(
+                    |reason| StunError {
+                        kind: StunErrorKind::Malformed {
+                            server: String::from(server),
+                            reason,
+                        },
+                    },
+                );
+            }
+            Err(_) => debug!(
+                "no response from stun server {} (attempt {})",
+                server, attempt
+            ),
+        }
+    }
+
+    Err(StunError {
+        kind: StunErrorKind::Timeout {
+            server: String::from(server),
+        },
+    })
+}
+
+/// Asks each configured STUN server in turn for the address our requests come from
+pub async fn stun_addr(config: &StunResolverConfig, ip_type: IpType) -> Result<IpAddr, StunError> {
+    let mut last_err = StunError {
+        kind: StunErrorKind::NoServers,
+    };
+
+    for server in &config.servers {
+        match query_server(server, ip_type).await {
+            Ok(addr) if IpType::from(addr) == ip_type => return Ok(addr),
+            Ok(addr) => warn!("stun server {} responded with {}", server, addr),
+            Err(e) => {
+                warn!("{}", e);
+                last_err = e;
+            }
+        }
+    }
+
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a binding response carrying the given address as a XOR-MAPPED-ADDRESS
+    fn binding_response(transaction_id: &[u8], addr: SocketAddr) -> Vec<u8> {
+        let mut mask = Vec::new();
+        mask.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask.extend_from_slice(transaction_id);
+
+        let (family, octets) = match addr.ip() {
+            IpAddr::V4(ip) => (0x01u8, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (0x02u8, ip.octets().to_vec()),
+        };
+
+        let mut value = vec![0, family];
+        value.extend_from_slice(&(addr.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        value.extend(octets.iter().zip(mask.iter()).map(|(x, m)| x ^ m));
+
+        let mut response = Vec::new();
+        response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(transaction_id);
+        response.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        response.extend_from_slice(&value);
+        response
+    }
+
+    #[test]
+    fn parse_xor_mapped_address() {
+        let id = transaction_id();
+        let v4: SocketAddr = "198.51.100.7:4321".parse().unwrap();
+        let response = binding_response(&id, v4);
+        assert_eq!(parse_binding_response(&response, &id), Ok(v4.ip()));
+
+        let v6: SocketAddr = "[2001:db8::7]:4321".parse().unwrap();
+        let response = binding_response(&id, v6);
+        assert_eq!(parse_binding_response(&response, &id), Ok(v6.ip()));
+
+        assert!(parse_binding_response(&response, &transaction_id()).is_err());
+        assert!(parse_binding_response(&response[..HEADER_LEN + 2], &id).is_err());
+    }
+
+    #[test]
+    fn parse_mapped_address() {
+        let id = transaction_id();
+        let mut response = Vec::new();
+        response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&20u16.to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&id);
+
+        // An unknown attribute that needs padding, then a MAPPED-ADDRESS
+        response.extend_from_slice(&[0x80, 0x22, 0x00, 0x03, b'a', b'b', b'c', 0x00]);
+        response.extend_from_slice(&ATTR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x01, 0x10, 0xe1, 203, 0, 113, 5]);
+
+        assert_eq!(
+            parse_binding_response(&response, &id),
+            Ok(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)))
+        );
+    }
+
+    #[tokio::test]
+    async fn stun_local_responder() {
+        let responder = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = responder.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 576];
+            let (received, from) = responder.recv_from(&mut buf).unwrap();
+            assert_eq!(received, HEADER_LEN);
+            assert_eq!(buf[..2], BINDING_REQUEST.to_be_bytes());
+            let response = binding_response(&buf[8..20], from);
+            responder.send_to(&response, from).unwrap();
+        });
+
+        let config = StunResolverConfig {
+            servers: vec![String::from("127.0.0.1:1"), addr.to_string()],
+        };
+
+        // The first server refuses or ignores the request, so the second is used
+        let ip = stun_addr(&config, IpType::V4).await.unwrap();
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+}
@@ -15,3 +15,56 @@ fn resolve_wan_on_no_arguments() {
         }
     }
 }
+
+#[test]
+fn json_report_on_stdout() {
+    // The WAN IP comes from a local server so that the test doesn't need network access
+    let server =
+        rouille::Server::new("localhost:0", |_request| rouille::Response::text("2.2.2.2")).unwrap();
+    let addr = server.server_addr();
+    let (handle, sender) = server.stoppable();
+
+    let config = format!(
+        "ip_resolver = \"http\"\n[resolvers.http]\nv4_url = \"http://{}/\"\n",
+        addr
+    );
+    let path = std::env::temp_dir().join(format!("dness-report-{}.toml", std::process::id()));
+    std::fs::write(&path, config).unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .arg("--config")
+        .arg(&path)
+        .args(["--output", "json"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let report: serde_json::Value = serde_json::from_str(stdout).unwrap();
+    assert_eq!(report["addresses"][0]["ip_type"], "4");
+    assert_eq!(report["addresses"][0]["address"], "2.2.2.2");
+    assert_eq!(report["success"], true);
+    assert!(output.status.success());
+
+    // The log is moved out of the way of the report
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("processed all"));
+}
+
+#[test]
+fn invalid_config_rejected_at_startup() {
+    let path = std::env::temp_dir().join(format!("dness-invalid-{}.toml", std::process::id()));
+    std::fs::write(&path, "[lookup]\nverify = true\nverify_interval = 0\n").unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd.arg("--config").arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.contains("verify_interval must be at least one second"));
+    assert!(!stdout.contains("resolved address to"));
+}